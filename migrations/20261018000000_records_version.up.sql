@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- 持ち駒の表し方を変える前のRECORDSはVERSION=1になる
+ALTER TABLE KIFU ADD COLUMN VERSION INTEGER NOT NULL DEFAULT 1;
@@ -15,7 +15,8 @@ def load_game_data():
     dbname = "db/data.db"
     conn = sqlite3.connect(dbname)
     cur = conn.cursor()
-    sql = "SELECT WINNER, RECORDS FROM KIFU"
+    # 古い形式(VERSION=1)のRECORDSはconvertで変換してから使う
+    sql = "SELECT WINNER, RECORDS FROM KIFU WHERE VERSION = 2"
     cur.execute(sql)
     game_data = cur.fetchall()
     cur.close()
@@ -26,6 +27,9 @@ def load_game_data():
     game_count = 0
     for row in game_data:
         (winner, binary) = row
+        # 引き分け(WINNER=2)は勝敗の学習に使わない
+        if winner not in (0, 1):
+            continue
         array_1d = np.frombuffer(binary, dtype=np.uint8)
         record = array_1d.reshape(
             [
@@ -0,0 +1,15 @@
+use anyhow::Result;
+use shogi_alg::db::{convert_legacy_kifu, get_connection};
+
+// 持ち駒を盤面の升に置いていた頃のKIFUテーブルのRECORDSを今の形式に変換する
+#[tokio::main]
+async fn main() -> Result<()> {
+    let pool = get_connection().await?;
+    sqlx::migrate!().run(&pool).await?;
+    let (converted, failed) = convert_legacy_kifu(&pool).await?;
+    for (id, e) in failed.iter() {
+        eprintln!("failed to convert kifu {}: {}", id, e);
+    }
+    println!("converted {} games, failed {}", converted, failed.len());
+    Ok(())
+}
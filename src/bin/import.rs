@@ -0,0 +1,85 @@
+use anyhow::{bail, Result};
+use shogi_alg::{
+    csa::parse_csa,
+    db::{get_connection, insert_kifu},
+    kif::{decode_kif, parse_kif},
+    record::{GameRecord, Termination},
+    validate::validate,
+};
+use std::path::{Path, PathBuf};
+
+// CSA(.csa)・KIF(.kif, .kifu)の棋譜をKIFUテーブルに取り込む
+// 引数にはファイルかディレクトリ(再帰的に探す)を指定する
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.is_empty() {
+        println!("usage: import <file or directory>...");
+        return Ok(());
+    }
+    let pool = get_connection().await?;
+    sqlx::migrate!().run(&pool).await?;
+
+    let mut files = vec![];
+    for arg in args {
+        collect_files(Path::new(&arg), &mut files)?;
+    }
+
+    let mut imported = 0;
+    let mut skipped = 0;
+    for path in files {
+        let record = match load_record(&path) {
+            Ok(record) => record,
+            Err(e) => {
+                println!("skip {}: {}", path.display(), e);
+                skipped += 1;
+                continue;
+            }
+        };
+        // 玉がないなど不正な局面から始まる棋譜は取り込まない
+        let errors = validate(&record.initial_boards, record.initial_turn);
+        if let Some(e) = errors.first() {
+            println!("skip {}: {}", path.display(), e);
+            skipped += 1;
+            continue;
+        }
+        // 中断などで結果が付いていない対局は学習に使えないので取り込まない
+        let winner = match (record.winner(), record.termination) {
+            (Some(winner), _) => Some(winner),
+            (None, Some(Termination::Sennichite | Termination::Jishogi)) => None,
+            _ => {
+                println!("skip {}: no result", path.display());
+                skipped += 1;
+                continue;
+            }
+        };
+        insert_kifu(&pool, winner, &record.boards_record()).await?;
+        imported += 1;
+    }
+    println!("imported {} games, skipped {}", imported, skipped);
+    Ok(())
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            collect_files(&entry?.path(), files)?;
+        }
+    } else if matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("csa" | "kif" | "kifu")
+    ) {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+// KIFはUTF-8(.kifu)とShift_JIS(.kif)のどちらも読める
+fn load_record(path: &Path) -> Result<GameRecord> {
+    let bytes = std::fs::read(path)?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("csa") => parse_csa(&String::from_utf8(bytes)?),
+        Some("kif" | "kifu") => parse_kif(&decode_kif(&bytes)?),
+        _ => bail!("unknown kifu format"),
+    }
+}
@@ -0,0 +1,57 @@
+use anyhow::{anyhow, Result};
+use shogi_alg::{
+    perft::{perft, perft_divide},
+    sfen::{parse_sfen, INITIAL_SFEN},
+    usi::move_to_usi,
+};
+use std::time::Instant;
+
+// 指し手生成の検証用に局面数を数える
+// usage: perft [--divide] <depth> [sfen]
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let divide = match args.iter().position(|a| a == "--divide") {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    };
+    if args.is_empty() {
+        println!("usage: perft [--divide] <depth> [sfen]");
+        return Ok(());
+    }
+    let depth = args[0]
+        .parse::<u32>()
+        .map_err(|_| anyhow!("invalid depth: {}", args[0]))?;
+    let sfen = if args.len() > 1 {
+        args[1..].join(" ")
+    } else {
+        INITIAL_SFEN.to_string()
+    };
+    let (boards, turn, _) = parse_sfen(&sfen)?;
+
+    let start = Instant::now();
+    let nodes = if divide {
+        let mut moves = perft_divide(&boards, turn, depth)
+            .into_iter()
+            .map(|(m, nodes)| (move_to_usi(&m), nodes))
+            .collect::<Vec<_>>();
+        moves.sort();
+        for (usi, nodes) in &moves {
+            println!("{}: {}", usi, nodes);
+        }
+        println!("moves: {}", moves.len());
+        moves.iter().map(|(_, nodes)| nodes).sum()
+    } else {
+        perft(&boards, turn, depth)
+    };
+    let elapsed = start.elapsed();
+    println!("nodes: {}", nodes);
+    println!(
+        "time: {}ms ({} nps)",
+        elapsed.as_millis(),
+        (nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64
+    );
+    Ok(())
+}
@@ -1,5 +1,12 @@
 use anyhow::Result;
-use shogi_alg::{db::get_connection, game::*, inference::Inference, piece::Color};
+use shogi_alg::{
+    board::{attack_map, threatened_pieces, Handicap},
+    db::get_connection,
+    game::*,
+    inference::Inference,
+    kif::to_kif,
+    piece::Color,
+};
 use std::{io::Write, sync::Arc};
 
 #[tokio::main]
@@ -10,6 +17,7 @@ async fn main() -> Result<()> {
 
 async fn run() -> Result<()> {
     let inference = Arc::new(Inference::init()?);
+    println!("{}", inference.load_message());
     let pool = get_connection().await?;
     sqlx::migrate!().run(&pool).await?;
 
@@ -27,7 +35,20 @@ async fn game_task(pool: sqlx::SqlitePool, inf: Arc<Inference>) -> Result<()> {
         _ => Color::White,
     };
 
-    let mut game = Game::new(pool, inf);
+    // 駒落ちは上手(後手)が駒を落とす
+    let names = Handicap::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, h)| format!("{}: {}", i, h.name()))
+        .collect::<Vec<_>>();
+    print!("Select Handicap ({}): ", names.join(", "));
+    std::io::stdout().flush()?;
+    let handicap = Handicap::ALL
+        .get(get_input() as usize)
+        .copied()
+        .unwrap_or(Handicap::Even);
+
+    let mut game = Game::with_handicap(pool, inf, handicap);
     loop {
         if player_color == game.current_turn() {
             let moves = game.get_legal_moves();
@@ -46,40 +67,119 @@ async fn game_task(pool: sqlx::SqlitePool, inf: Arc<Inference>) -> Result<()> {
                         println!("[{}]: {} => to [{}, {}] 打", i, m.0, m.1.to.x, m.1.to.y)
                     }
                 });
+                // 入玉宣言できる場合は指し手の後の番号で宣言する
+                let can_declare = game.declaration().is_some();
+                if can_declare {
+                    println!("[{}]: 入玉宣言", moves.len());
+                }
+                let threats_index = moves.len() + can_declare as usize;
+                println!("[{}]: 利きと取られそうな駒を表示", threats_index);
+                // 自分の指した手があれば相手の手と合わせて待ったできる
+                let can_undo = game.move_count() >= 2;
+                if can_undo {
+                    println!("[{}]: 待った", threats_index + 1);
+                }
+                let choices = threats_index + 1 + can_undo as usize;
 
                 let index = loop {
                     print!("Select Move: ");
                     std::io::stdout().flush()?;
                     let selected_num = get_input();
-                    if selected_num >= moves.len() as u64 {
-                        println!("Allow Range = 0..{}", moves.len() - 1);
+                    if selected_num >= choices as u64 {
+                        println!("Allow Range = 0..{}", choices - 1);
                         continue;
                     }
                     break selected_num;
                 };
-                game.play_next(&moves[index as usize].1);
+                if index as usize == threats_index {
+                    print_threats(&game, player_color);
+                    continue;
+                }
+                if can_undo && index as usize == choices - 1 {
+                    game.undo();
+                    game.undo();
+                    continue;
+                }
+                if index as usize == moves.len() {
+                    game.declare();
+                    print_result(&game, player_color);
+                    break;
+                }
+                if game.play_next(&moves[index as usize].1) != GameState::Playing {
+                    print_result(&game, player_color);
+                    break;
+                }
             } else {
                 println!("You Lose!");
                 game.print();
                 break;
             }
-        } else {
-            match game.next()? {
-                GameState::Checkmate(color) => {
-                    if color == player_color {
-                        println!("You Win");
-                    } else {
-                        println!("You Lose");
-                    }
-                    game.print();
-                    break;
-                }
-                _ => {}
-            }
+        } else if game.next()? != GameState::Playing {
+            print_result(&game, player_color);
+            break;
         }
     }
 
     game.save().await?;
+    save_kif(&game)?;
+    Ok(())
+}
+
+fn print_result(game: &Game, player_color: Color) {
+    match game.state() {
+        GameState::Checkmate(color)
+        | GameState::PerpetualCheck(color)
+        | GameState::EnteringKing(color) => {
+            if color == player_color {
+                println!("You Win");
+            } else {
+                println!("You Lose");
+            }
+        }
+        GameState::Draw | GameState::Jishogi => println!("Draw"),
+        GameState::Playing => {}
+    }
+    game.print();
+}
+
+// 升ごとの利きの数(先手/後手)と、取られると損をする駒を表示する
+fn print_threats(game: &Game, player_color: Color) {
+    let boards = game.boards();
+    let [black, white] = attack_map(boards);
+    println!("attack count (\x1b[31m先手\x1b[m/後手)");
+    for (black_row, white_row) in black.iter().zip(white.iter()) {
+        let row = black_row
+            .iter()
+            .zip(white_row)
+            .map(|(b, w)| format!("\x1b[31m{}\x1b[m/{}", b, w))
+            .collect::<Vec<_>>();
+        println!("| {} |", row.join(" | "));
+    }
+    for (color, name) in [
+        (player_color, "Your"),
+        (player_color.opponent(), "Opponent's"),
+    ] {
+        let threatened = threatened_pieces(boards, color)
+            .iter()
+            .map(|p| {
+                let piece = boards.board[p.y as usize][p.x as usize].unwrap();
+                format!("{} [{}, {}]", piece, p.x, p.y)
+            })
+            .collect::<Vec<_>>();
+        if threatened.is_empty() {
+            println!("{} threatened pieces: none", name);
+        } else {
+            println!("{} threatened pieces: {}", name, threatened.join(", "));
+        }
+    }
+}
+
+// 対局をKIF形式(UTF-8)でkifuディレクトリに保存する
+fn save_kif(game: &Game) -> Result<()> {
+    std::fs::create_dir_all("kifu")?;
+    let path = format!("kifu/{}.kifu", chrono::Local::now().format("%Y%m%d_%H%M%S"));
+    std::fs::write(&path, to_kif(&game.record()))?;
+    println!("saved {}", path);
     Ok(())
 }
 
@@ -1,5 +1,8 @@
-use anyhow::Result;
-use shogi_alg::{db::get_connection, game::*, inference::Inference, piece::Color};
+use anyhow::{anyhow, Result};
+use shogi_alg::{
+    board::Handicap, db::get_connection, game::*, inference::Inference, mcts::MctsConfig,
+    piece::Color,
+};
 use std::{sync::Arc, time::Duration};
 
 const HAND_TIME: Duration = Duration::from_secs(10 * 60);
@@ -7,32 +10,38 @@ const GAME_COUNT: usize = 10000;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // 引数で手合割(香落ちなど)を指定すると駒落ちで自己対局する
+    let handicap = match std::env::args().nth(1) {
+        Some(name) => Handicap::from_name(&name).ok_or(anyhow!("unknown handicap: {}", name))?,
+        None => Handicap::Even,
+    };
     let pool = get_connection().await?;
     let inf: Arc<Inference> = Arc::new(Inference::init()?);
+    println!("{}", inf.load_message());
     sqlx::migrate!().run(&pool).await?;
-    if inf.is_use_model() {
-        for i in 0..GAME_COUNT {
-            println!("start game({})", i);
-            train_task(pool.clone(), inf.clone()).await?;
-            println!("end game({})", i);
-        }
-    } else {
-        println!("start game");
-        train_task(pool.clone(), inf.clone()).await?;
-        println!("end game");
+    // モデルがない場合も手作りの評価関数で対局して最初の学習データを作る
+    for i in 0..GAME_COUNT {
+        println!("start game({})", i);
+        train_task(pool.clone(), inf.clone(), handicap).await?;
+        println!("end game({})", i);
     }
     Ok(())
 }
 
-async fn train_task(pool: sqlx::sqlite::SqlitePool, inf: Arc<Inference>) -> Result<()> {
-    let mut game = Game::new(pool, inf);
+async fn train_task(
+    pool: sqlx::sqlite::SqlitePool,
+    inf: Arc<Inference>,
+    handicap: Handicap,
+) -> Result<()> {
+    let mut game = Game::with_handicap(pool, inf, handicap);
+    let config = MctsConfig::default();
     let mut hand_count: usize = 1;
     let mut black_hand_time = HAND_TIME;
     let mut white_hand_time = HAND_TIME;
     let mut start_black = std::time::Instant::now();
     let mut start_white = std::time::Instant::now();
     loop {
-        let result = game.next()?;
+        let result = game.next_with_mcts(&config)?;
         match game.current_turn() {
             Color::Black => {
                 let elapsed: Duration = start_white.elapsed();
@@ -54,16 +63,24 @@ async fn train_task(pool: sqlx::sqlite::SqlitePool, inf: Arc<Inference>) -> Resu
             }
         }
         match result {
-            GameState::Checkmate(_color) => {
-                break;
+            GameState::Playing => {
+                hand_count += 1;
             }
             _ => {
-                hand_count += 1;
+                break;
             }
         }
     }
     game.print();
-    println!("{:?} win.({} hands)", game.current_turn(), hand_count);
+    match game.state() {
+        GameState::Checkmate(color)
+        | GameState::PerpetualCheck(color)
+        | GameState::EnteringKing(color) => {
+            println!("{:?} win.({} hands)", color, hand_count)
+        }
+        GameState::Draw | GameState::Jishogi => println!("draw.({} hands)", hand_count),
+        GameState::Playing => println!("{:?} win.({} hands)", game.current_turn(), hand_count),
+    }
     game.save().await?;
     Ok(())
 }
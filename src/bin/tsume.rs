@@ -0,0 +1,44 @@
+use anyhow::{anyhow, Result};
+use shogi_alg::{
+    sfen::parse_sfen,
+    tsume::{solve, TsumeResult},
+    usi::move_to_usi,
+};
+use std::time::Instant;
+
+// 探索するノード数の既定値
+const DEFAULT_MAX_NODES: u64 = 10_000_000;
+
+// 手番の側が攻め方の詰将棋を解く
+// usage: tsume [--nodes <n>] <sfen>
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let max_nodes = match args.iter().position(|a| a == "--nodes") {
+        Some(i) if i + 1 < args.len() => {
+            let nodes = args[i + 1]
+                .parse::<u64>()
+                .map_err(|_| anyhow!("invalid node count: {}", args[i + 1]))?;
+            args.drain(i..i + 2);
+            nodes
+        }
+        _ => DEFAULT_MAX_NODES,
+    };
+    if args.is_empty() {
+        println!("usage: tsume [--nodes <n>] <sfen>");
+        return Ok(());
+    }
+    // 詰将棋では攻め方の玉がないことが多いので局面は検証しない
+    let (boards, turn, _) = parse_sfen(&args.join(" "))?;
+
+    let start = Instant::now();
+    match solve(&boards, turn, max_nodes) {
+        TsumeResult::Mate(moves) => {
+            let moves = moves.iter().map(move_to_usi).collect::<Vec<_>>();
+            println!("mate in {}: {}", moves.len(), moves.join(" "));
+        }
+        TsumeResult::NoMate => println!("no mate"),
+        TsumeResult::Unknown => println!("unknown (max nodes {})", max_nodes),
+    }
+    println!("time: {}ms", start.elapsed().as_millis());
+    Ok(())
+}
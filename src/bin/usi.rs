@@ -0,0 +1,354 @@
+use anyhow::{bail, Result};
+use shogi_alg::{
+    board::{Declaration, ImpasseRule},
+    game::Game,
+    inference::Inference,
+    piece::Color,
+    search::{SearchLimits, SearchResult, MATE, MAX_DEPTH},
+    sfen::INITIAL_SFEN,
+    tsume::{self, TsumeResult},
+    tt::TranspositionTable,
+    usi::{move_from_usi, move_to_usi},
+};
+use std::{
+    io::{BufRead, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
+
+const ENGINE_NAME: &str = "shogi-ml";
+const ENGINE_AUTHOR: &str = "ion0658";
+// 置換表の大きさ (MB) の既定値 (USI_Hashで変更できる)
+const DEFAULT_HASH_MB: usize = 256;
+const MAX_HASH_MB: usize = 32768;
+// 深さも時間も指定されていないときに読む深さ
+const DEFAULT_DEPTH: u32 = 3;
+// 通信の遅れを見込んで使える時間から引く時間 (ms)
+const TIME_MARGIN_MS: u64 = 300;
+// 1手に使う時間の下限 (ms)
+const MIN_THINK_MS: u64 = 50;
+// go mateで探索するノード数の上限
+const MATE_MAX_NODES: u64 = 1_000_000;
+
+// メインループが受け取るもの (標準入力の行と思考スレッドの結果)
+enum Event {
+    Command(String),
+    // 思考が終わったときに返すコマンド (bestmove・checkmate)
+    Done(String),
+}
+
+// 思考中の状態
+struct Thinking {
+    stop: Arc<AtomicBool>,
+    // infinite・ponderのときはstopが来るまでbestmoveを返さない
+    wait_stop: bool,
+    // stopを待っている間に読み終えた結果
+    reply: Option<String>,
+}
+
+// goコマンドの引数
+#[derive(Debug, Default)]
+struct GoOptions {
+    btime: Option<u64>,
+    wtime: Option<u64>,
+    binc: Option<u64>,
+    winc: Option<u64>,
+    byoyomi: Option<u64>,
+    depth: Option<u64>,
+    infinite: bool,
+    ponder: bool,
+    mate: bool,
+}
+
+impl GoOptions {
+    fn parse(args: &[&str]) -> Self {
+        let mut options = GoOptions::default();
+        let mut iter = args.iter();
+        while let Some(&arg) = iter.next() {
+            let mut value = || iter.next().and_then(|v| v.parse::<u64>().ok());
+            match arg {
+                "btime" => options.btime = value(),
+                "wtime" => options.wtime = value(),
+                "binc" => options.binc = value(),
+                "winc" => options.winc = value(),
+                "byoyomi" => options.byoyomi = value(),
+                "depth" => options.depth = value(),
+                "infinite" => options.infinite = true,
+                "ponder" => options.ponder = true,
+                "mate" => {
+                    options.mate = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        options
+    }
+
+    // 手番側がこの手に使える時間
+    fn time_limit(&self, turn: Color) -> Option<Duration> {
+        let (time, inc) = match turn {
+            Color::Black => (self.btime, self.binc),
+            Color::White => (self.wtime, self.winc),
+        };
+        if time.is_none() && self.byoyomi.is_none() && inc.is_none() {
+            return None;
+        }
+        let ms = time.unwrap_or(0) / 40 + inc.unwrap_or(0) + self.byoyomi.unwrap_or(0);
+        Some(Duration::from_millis(
+            ms.saturating_sub(TIME_MARGIN_MS).max(MIN_THINK_MS),
+        ))
+    }
+
+    fn search_limits(&self, turn: Color, stop: Arc<AtomicBool>) -> SearchLimits {
+        // infinite・ponderはstopが来るまで読み続ける
+        let time = if self.infinite || self.ponder {
+            None
+        } else {
+            self.time_limit(turn)
+        };
+        let depth = match (self.depth, time) {
+            (Some(depth), _) => depth as u32,
+            (None, None) if !self.infinite && !self.ponder => DEFAULT_DEPTH,
+            (None, _) => SearchLimits::default().depth,
+        };
+        SearchLimits {
+            depth,
+            time,
+            quiescence_checks: false,
+            stop: Some(stop),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // USIの対局は棋譜のDBに保存しないので、DBファイルは開かない
+    let pool = sqlx::SqlitePool::connect_lazy("sqlite::memory:")?;
+
+    // 思考中もstopやquitを受け取れるように標準入力は別スレッドで読む
+    let (tx, rx) = mpsc::channel::<Event>();
+    let input_tx = tx.clone();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if input_tx.send(Event::Command(line)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut inference: Option<Arc<Inference>> = None;
+    let mut game: Option<Arc<Game>> = None;
+    let mut hash_mb = DEFAULT_HASH_MB;
+    let mut tt: Option<Arc<TranspositionTable>> = None;
+    let mut thinking: Option<Thinking> = None;
+    while let Ok(event) = rx.recv() {
+        let line = match event {
+            Event::Command(line) => line,
+            Event::Done(reply) => {
+                match &mut thinking {
+                    Some(t) if t.wait_stop => t.reply = Some(reply),
+                    _ => {
+                        send(&reply);
+                        thinking = None;
+                    }
+                }
+                continue;
+            }
+        };
+        let args = line.split_whitespace().collect::<Vec<_>>();
+        let Some((&command, args)) = args.split_first() else {
+            continue;
+        };
+        match command {
+            "usi" => {
+                send(&format!("id name {}", ENGINE_NAME));
+                send(&format!("id author {}", ENGINE_AUTHOR));
+                send(&format!(
+                    "option name USI_Hash type spin default {} min 1 max {}",
+                    DEFAULT_HASH_MB, MAX_HASH_MB
+                ));
+                send("usiok");
+            }
+            "isready" => {
+                if inference.is_none() {
+                    let inf = Inference::init()?;
+                    send(&format!("info string {}", inf.load_message()));
+                    inference = Some(Arc::new(inf));
+                }
+                if tt.is_none() {
+                    tt = Some(Arc::new(TranspositionTable::new(hash_mb)));
+                }
+                send("readyok");
+            }
+            // setoption name USI_Hash value <MB>
+            "setoption" => {
+                if let ["name", "USI_Hash", "value", value] = args {
+                    match value
+                        .parse()
+                        .ok()
+                        .filter(|mb| (1..=MAX_HASH_MB).contains(mb))
+                    {
+                        Some(mb) if mb != hash_mb => {
+                            hash_mb = mb;
+                            tt = None;
+                        }
+                        Some(_) => {}
+                        None => send(&format!("info string invalid USI_Hash: {}", value)),
+                    }
+                }
+            }
+            "usinewgame" => {
+                game = None;
+                if let Some(tt) = &tt {
+                    tt.clear();
+                }
+            }
+            "position" => {
+                let Some(inf) = &inference else {
+                    send("info string isready is required before position");
+                    continue;
+                };
+                match set_position(pool.clone(), inf.clone(), args) {
+                    Ok(g) => game = Some(Arc::new(g)),
+                    Err(e) => {
+                        send(&format!("info string {}", e));
+                        game = None;
+                    }
+                }
+            }
+            "go" => {
+                let options = GoOptions::parse(args);
+                if thinking.is_some() {
+                    send("info string already thinking");
+                    continue;
+                }
+                let (Some(game), Some(tt)) = (&game, &tt) else {
+                    send(if options.mate {
+                        "checkmate nomate"
+                    } else {
+                        "bestmove resign"
+                    });
+                    continue;
+                };
+                // 思考は別スレッドで行い、終わったら返すコマンドをメインループに送る
+                let stop = Arc::new(AtomicBool::new(false));
+                let limits = options.search_limits(game.current_turn(), stop.clone());
+                let (game, tt, tx) = (game.clone(), tt.clone(), tx.clone());
+                std::thread::spawn(move || {
+                    let reply = if options.mate {
+                        checkmate(&game)
+                    } else {
+                        let bestmove = think(&game, &tt, limits).unwrap_or_else(|e| {
+                            send(&format!("info string {}", e));
+                            "resign".to_string()
+                        });
+                        format!("bestmove {}", bestmove)
+                    };
+                    let _ = tx.send(Event::Done(reply));
+                });
+                thinking = Some(Thinking {
+                    stop,
+                    wait_stop: !options.mate && (options.infinite || options.ponder),
+                    reply: None,
+                });
+            }
+            "stop" | "ponderhit" => {
+                if let Some(t) = &mut thinking {
+                    t.stop.store(true, Ordering::Relaxed);
+                    t.wait_stop = false;
+                    if let Some(reply) = t.reply.take() {
+                        send(&reply);
+                        thinking = None;
+                    }
+                }
+            }
+            "quit" => {
+                if let Some(t) = &thinking {
+                    t.stop.store(true, Ordering::Relaxed);
+                }
+                break;
+            }
+            // gameoverと他のsetoptionは受け取るだけ
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn think(game: &Game, tt: &TranspositionTable, limits: SearchLimits) -> Result<String> {
+    // 入玉宣言勝ちできる場合は宣言する
+    if game.declaration() == Some(Declaration::Win) {
+        return Ok("win".to_string());
+    }
+    let result = game.search(tt, limits, |result| send_info(result, tt))?;
+    let bestmove = match result.best_move {
+        Some(m) => move_to_usi(&m),
+        None => "resign".to_string(),
+    };
+    Ok(bestmove)
+}
+
+// go mateの結果 (時間の指定は使わず、ノード数の上限で打ち切る)
+fn checkmate(game: &Game) -> String {
+    match tsume::solve(game.boards(), game.current_turn(), MATE_MAX_NODES) {
+        TsumeResult::Mate(moves) => {
+            let moves = moves.iter().map(move_to_usi).collect::<Vec<_>>();
+            format!("checkmate {}", moves.join(" "))
+        }
+        TsumeResult::NoMate => "checkmate nomate".to_string(),
+        TsumeResult::Unknown => "checkmate timeout".to_string(),
+    }
+}
+
+fn send_info(result: &SearchResult, tt: &TranspositionTable) {
+    let pv = result.pv.iter().map(move_to_usi).collect::<Vec<_>>();
+    let score = if result.score.abs() >= MATE - MAX_DEPTH as i32 {
+        // 詰みまでの手数 (詰まされる場合は負の値)
+        let plies = MATE - result.score.abs();
+        format!("mate {}", if result.score > 0 { plies } else { -plies })
+    } else {
+        format!("cp {}", result.score)
+    };
+    send(&format!(
+        "info depth {} score {} nodes {} time {} hashfull {} pv {}",
+        result.depth,
+        score,
+        result.nodes,
+        result.time.as_millis(),
+        tt.hashfull(),
+        pv.join(" ")
+    ));
+}
+
+// position [startpos | sfen <sfen>] [moves <move1> ...]
+fn set_position(pool: sqlx::SqlitePool, inf: Arc<Inference>, args: &[&str]) -> Result<Game> {
+    let moves_index = args.iter().position(|&a| a == "moves");
+    let (position, moves) = match moves_index {
+        Some(i) => (&args[..i], &args[i + 1..]),
+        None => (args, &[][..]),
+    };
+    let sfen = match position.split_first() {
+        Some((&"startpos", _)) => INITIAL_SFEN.to_string(),
+        Some((&"sfen", sfen)) => sfen.join(" "),
+        _ => bail!("invalid position command: {}", args.join(" ")),
+    };
+    let mut game = Game::from_sfen(pool, inf, &sfen)?;
+    // USIの入玉宣言は27点法
+    game.set_impasse_rule(ImpasseRule::Point27);
+    for usi in moves {
+        let m = move_from_usi(game.boards(), game.current_turn(), usi)?;
+        game.play_next(&m);
+    }
+    Ok(game)
+}
+
+fn send(message: &str) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{}", message);
+    let _ = stdout.flush();
+}
@@ -0,0 +1,1043 @@
+use crate::{
+    board::{self, is_in_enemy_camp, Boards, Hand, LegalMove, BOARD_SIZE, HAND_PIECE_TYPES},
+    eval::piece_value,
+    piece::{Color, Piece, PieceType},
+    zobrist::{hand_key, piece_key, turn_key},
+};
+use std::{
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not},
+    sync::OnceLock,
+};
+
+// 升の数
+pub const SQUARE_COUNT: usize = BOARD_SIZE * BOARD_SIZE;
+// 駒の種類の数 (成り駒を含む)
+const PIECE_TYPE_COUNT: usize = PieceType::get_max() as usize;
+// 回転bitboardの線の向き (0: 段, 1: 筋, 2: 斜め, 3: 逆斜め)
+const LINE_DIRECTIONS: [(i32, i32); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+const RANK: usize = 0;
+const FILE: usize = 1;
+const DIAGONAL: usize = 2;
+const ANTI_DIAGONAL: usize = 3;
+
+pub(crate) const PIECE_TYPES: [PieceType; PIECE_TYPE_COUNT] = [
+    PieceType::King,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Gold,
+    PieceType::Silver,
+    PieceType::Knight,
+    PieceType::Lance,
+    PieceType::Pawn,
+    PieceType::Dragon,
+    PieceType::Horse,
+    PieceType::PromotedSilver,
+    PieceType::PromotedKnight,
+    PieceType::PromotedLance,
+    PieceType::PromotedPawn,
+];
+
+// 升の番号 (y * 9 + x、座標はBoardsと同じ)
+pub const fn square(x: usize, y: usize) -> usize {
+    y * BOARD_SIZE + x
+}
+
+// 升の番号から座標(x, y)を返す関数
+pub const fn square_xy(sq: usize) -> (usize, usize) {
+    (sq % BOARD_SIZE, sq / BOARD_SIZE)
+}
+
+// 81升それぞれを1bitで表す
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Bitboard(pub u128);
+
+impl Bitboard {
+    pub const EMPTY: Bitboard = Bitboard(0);
+    pub const ALL: Bitboard = Bitboard((1 << SQUARE_COUNT) - 1);
+
+    pub const fn from_square(sq: usize) -> Bitboard {
+        Bitboard(1 << sq)
+    }
+
+    pub const fn contains(self, sq: usize) -> bool {
+        self.0 & (1 << sq) != 0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    // 番号の小さい升から順に返す
+    pub fn squares(self) -> impl Iterator<Item = usize> {
+        let mut bits = self.0;
+        std::iter::from_fn(move || {
+            if bits == 0 {
+                return None;
+            }
+            let sq = bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            Some(sq)
+        })
+    }
+}
+
+impl BitAnd for Bitboard {
+    type Output = Bitboard;
+    fn bitand(self, rhs: Bitboard) -> Bitboard {
+        Bitboard(self.0 & rhs.0)
+    }
+}
+
+impl BitOr for Bitboard {
+    type Output = Bitboard;
+    fn bitor(self, rhs: Bitboard) -> Bitboard {
+        Bitboard(self.0 | rhs.0)
+    }
+}
+
+impl BitXor for Bitboard {
+    type Output = Bitboard;
+    fn bitxor(self, rhs: Bitboard) -> Bitboard {
+        Bitboard(self.0 ^ rhs.0)
+    }
+}
+
+impl Not for Bitboard {
+    type Output = Bitboard;
+    fn not(self) -> Bitboard {
+        Bitboard(!self.0 & Bitboard::ALL.0)
+    }
+}
+
+impl BitAndAssign for Bitboard {
+    fn bitand_assign(&mut self, rhs: Bitboard) {
+        self.0 &= rhs.0;
+    }
+}
+
+impl BitOrAssign for Bitboard {
+    fn bitor_assign(&mut self, rhs: Bitboard) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitXorAssign for Bitboard {
+    fn bitxor_assign(&mut self, rhs: Bitboard) {
+        self.0 ^= rhs.0;
+    }
+}
+
+// 事前に計算した利きのテーブル
+struct AttackTables {
+    // 近接駒の利き [手番][駒の種類][升]
+    steps: [[[Bitboard; SQUARE_COUNT]; PIECE_TYPE_COUNT]; 2],
+    // 香の進む方向の升 [手番][升]
+    forward: [[Bitboard; SQUARE_COUNT]; 2],
+    // 回転bitboardでの升の位置 [線の向き][升]
+    rotated_index: [[u8; SQUARE_COUNT]; 4],
+    // 升を含む線の回転bitboardでの開始位置と長さ [線の向き][升]
+    line_start: [[u8; SQUARE_COUNT]; 4],
+    line_len: [[u8; SQUARE_COUNT]; 4],
+    // 線上の占有状態ごとの利き [線の向き * 81 + 升][占有]
+    lines: Vec<Vec<Bitboard>>,
+    ranks: [Bitboard; BOARD_SIZE],
+    files: [Bitboard; BOARD_SIZE],
+    // 同じ線上にある2升の間の升 [升 * 81 + 升] (線上にない場合は空)
+    between: Vec<Bitboard>,
+}
+
+fn tables() -> &'static AttackTables {
+    static TABLES: OnceLock<AttackTables> = OnceLock::new();
+    TABLES.get_or_init(init_tables)
+}
+
+fn init_tables() -> AttackTables {
+    let on_board =
+        |x: i32, y: i32| (0..BOARD_SIZE as i32).contains(&x) && (0..BOARD_SIZE as i32).contains(&y);
+
+    let mut steps = [[[Bitboard::EMPTY; SQUARE_COUNT]; PIECE_TYPE_COUNT]; 2];
+    for color in [Color::Black, Color::White] {
+        // 後手は先手の動きを上下反転したもの
+        let sign = match color {
+            Color::Black => 1,
+            Color::White => -1,
+        };
+        for piece_type in PIECE_TYPES {
+            let vectors: &[(i32, i32)] = match piece_type {
+                PieceType::King | PieceType::Dragon | PieceType::Horse => &[
+                    (-1, -1),
+                    (-1, 0),
+                    (-1, 1),
+                    (0, -1),
+                    (0, 1),
+                    (1, -1),
+                    (1, 0),
+                    (1, 1),
+                ],
+                PieceType::Gold
+                | PieceType::PromotedSilver
+                | PieceType::PromotedKnight
+                | PieceType::PromotedLance
+                | PieceType::PromotedPawn => &[(-1, 0), (-1, 1), (0, -1), (0, 1), (1, 0), (1, 1)],
+                PieceType::Silver => &[(-1, -1), (-1, 1), (0, 1), (1, -1), (1, 1)],
+                PieceType::Knight => &[(-1, 2), (1, 2)],
+                PieceType::Pawn => &[(0, 1)],
+                // 飛び駒はlinesで扱う
+                _ => &[],
+            };
+            let table = &mut steps[color as usize][piece_type as usize - 1];
+            for (sq, attacks) in table.iter_mut().enumerate() {
+                let (x, y) = square_xy(sq);
+                for (dx, dy) in vectors {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy * sign);
+                    if on_board(nx, ny) {
+                        *attacks |= Bitboard::from_square(square(nx as usize, ny as usize));
+                    }
+                }
+            }
+        }
+    }
+
+    let forward = [Color::Black, Color::White].map(|color| {
+        std::array::from_fn(|sq| {
+            let (x, y) = square_xy(sq);
+            let ys = match color {
+                Color::Black => y + 1..BOARD_SIZE,
+                Color::White => 0..y,
+            };
+            ys.fold(Bitboard::EMPTY, |b, ny| {
+                b | Bitboard::from_square(square(x, ny))
+            })
+        })
+    });
+    let mut ranks = [Bitboard::EMPTY; BOARD_SIZE];
+    let mut files = [Bitboard::EMPTY; BOARD_SIZE];
+    for sq in 0..SQUARE_COUNT {
+        let (x, y) = square_xy(sq);
+        ranks[y] |= Bitboard::from_square(sq);
+        files[x] |= Bitboard::from_square(sq);
+    }
+
+    // 各向きの線が回転bitboard上で連続するように升を並べ替える
+    let mut rotated_index = [[0; SQUARE_COUNT]; 4];
+    let mut line_start = [[0; SQUARE_COUNT]; 4];
+    let mut line_len = [[0; SQUARE_COUNT]; 4];
+    let mut lines = vec![vec![]; 4 * SQUARE_COUNT];
+    for (direction, (dx, dy)) in LINE_DIRECTIONS.iter().enumerate() {
+        let mut next_index = 0;
+        for start in 0..SQUARE_COUNT {
+            let (x, y) = square_xy(start);
+            // 線の端の升からだけ線をたどる
+            if on_board(x as i32 - dx, y as i32 - dy) {
+                continue;
+            }
+            let mut line = vec![];
+            let (mut lx, mut ly) = (x as i32, y as i32);
+            while on_board(lx, ly) {
+                line.push(square(lx as usize, ly as usize));
+                lx += dx;
+                ly += dy;
+            }
+            for (i, &sq) in line.iter().enumerate() {
+                rotated_index[direction][sq] = (next_index + i) as u8;
+                line_start[direction][sq] = next_index as u8;
+                line_len[direction][sq] = line.len() as u8;
+                lines[direction * SQUARE_COUNT + sq] = (0..1usize << line.len())
+                    .map(|occupied| line_attacks(&line, i, occupied))
+                    .collect();
+            }
+            next_index += line.len();
+        }
+    }
+
+    let mut between = vec![Bitboard::EMPTY; SQUARE_COUNT * SQUARE_COUNT];
+    for from in 0..SQUARE_COUNT {
+        let (x, y) = square_xy(from);
+        for (dx, dy) in [
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (-1, -1),
+            (1, -1),
+            (-1, 1),
+        ] {
+            let mut squares = Bitboard::EMPTY;
+            let (mut nx, mut ny) = (x as i32 + dx, y as i32 + dy);
+            while on_board(nx, ny) {
+                let to = square(nx as usize, ny as usize);
+                between[from * SQUARE_COUNT + to] = squares;
+                squares |= Bitboard::from_square(to);
+                nx += dx;
+                ny += dy;
+            }
+        }
+    }
+
+    AttackTables {
+        steps,
+        forward,
+        rotated_index,
+        line_start,
+        line_len,
+        lines,
+        ranks,
+        files,
+        between,
+    }
+}
+
+// 線上のi番目の升から両方向に、最初にぶつかる駒の升までの利きを求める関数
+// occupiedは線上の升ごとの占有状態
+fn line_attacks(line: &[usize], i: usize, occupied: usize) -> Bitboard {
+    let mut attacks = Bitboard::EMPTY;
+    for (j, &sq) in line.iter().enumerate().skip(i + 1) {
+        attacks |= Bitboard::from_square(sq);
+        if occupied & (1 << j) != 0 {
+            break;
+        }
+    }
+    for (j, &sq) in line.iter().enumerate().take(i).rev() {
+        attacks |= Bitboard::from_square(sq);
+        if occupied & (1 << j) != 0 {
+            break;
+        }
+    }
+    attacks
+}
+
+// bitboardでの指し手
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Move {
+    // 盤上の駒を動かす
+    Normal {
+        from: usize,
+        to: usize,
+        promote: bool,
+    },
+    // 持ち駒を打つ
+    Drop {
+        piece_type: PieceType,
+        to: usize,
+    },
+}
+
+impl Move {
+    pub const fn to(&self) -> usize {
+        match self {
+            Move::Normal { to, .. } | Move::Drop { to, .. } => *to,
+        }
+    }
+
+    // Boardsでの指し手から変換する
+    pub fn from_legal_move(m: &LegalMove) -> Move {
+        let to = square(m.to.x as usize, m.to.y as usize);
+        if let Some(piece) = m.drop_piece() {
+            Move::Drop {
+                piece_type: piece.piece_type,
+                to,
+            }
+        } else {
+            Move::Normal {
+                from: square(m.from.x as usize, m.from.y as usize),
+                to,
+                promote: m.revolute,
+            }
+        }
+    }
+
+    // Boardsでの指し手に変換する (打つ手の場合はturnの側が打つ)
+    pub fn to_legal_move(&self, turn: Color) -> LegalMove {
+        let to_position = |sq: usize| {
+            let (x, y) = square_xy(sq);
+            board::Position::new(x as i32, y as i32, 0)
+        };
+        match *self {
+            Move::Normal { from, to, promote } => LegalMove {
+                from: to_position(from),
+                to: to_position(to),
+                revolute: promote,
+            },
+            Move::Drop { piece_type, to } => {
+                LegalMove::drop(Piece::new(piece_type, turn), to_position(to))
+            }
+        }
+    }
+}
+
+// bitboardによる局面 (盤面・持ち駒・手番)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    // 手番・駒の種類ごとの駒の位置 [手番][駒の種類]
+    pieces: [[Bitboard; PIECE_TYPE_COUNT]; 2],
+    // 手番ごとの駒の位置
+    colors: [Bitboard; 2],
+    // 回転bitboardによる占有状態 [線の向き] (段の向きは通常の並び)
+    rotated: [u128; 4],
+    // 升ごとの駒
+    squares: [Option<Piece>; SQUARE_COUNT],
+    hand: Hand,
+    turn: Color,
+    // Zobristハッシュ (駒の移動のたびに差分で更新する)
+    key: u64,
+}
+
+impl Position {
+    fn empty(turn: Color) -> Position {
+        Position {
+            pieces: [[Bitboard::EMPTY; PIECE_TYPE_COUNT]; 2],
+            colors: [Bitboard::EMPTY; 2],
+            rotated: [0; 4],
+            squares: [None; SQUARE_COUNT],
+            hand: Hand::default(),
+            turn,
+            key: match turn {
+                Color::Black => 0,
+                Color::White => turn_key(),
+            },
+        }
+    }
+
+    pub fn from_boards(boards: &Boards, turn: Color) -> Position {
+        let mut position = Position::empty(turn);
+        for (y, row) in boards.board.iter().enumerate() {
+            for (x, piece) in row.iter().enumerate() {
+                if let Some(piece) = piece {
+                    position.put_piece(square(x, y), *piece);
+                }
+            }
+        }
+        position.hand = boards.hand;
+        for color in [Color::Black, Color::White] {
+            for piece_type in HAND_PIECE_TYPES {
+                position.key ^= hand_key(
+                    piece_type,
+                    color,
+                    boards.hand.count(piece_type, color) as usize,
+                );
+            }
+        }
+        position
+    }
+
+    pub fn to_boards(&self) -> Boards {
+        let mut boards = Boards::empty();
+        for (sq, piece) in self.squares.iter().enumerate() {
+            let (x, y) = square_xy(sq);
+            boards.board[y][x] = *piece;
+        }
+        boards.hand = self.hand;
+        boards
+    }
+
+    pub const fn turn(&self) -> Color {
+        self.turn
+    }
+
+    // 局面のZobristハッシュ (zobrist::hash と同じ値になる)
+    pub const fn key(&self) -> u64 {
+        self.key
+    }
+
+    pub const fn piece_on(&self, sq: usize) -> Option<Piece> {
+        self.squares[sq]
+    }
+
+    pub fn pieces(&self, piece_type: PieceType, color: Color) -> Bitboard {
+        self.pieces[color as usize][piece_type as usize - 1]
+    }
+
+    pub const fn color_pieces(&self, color: Color) -> Bitboard {
+        self.colors[color as usize]
+    }
+
+    pub const fn occupied(&self) -> Bitboard {
+        Bitboard(self.rotated[RANK])
+    }
+
+    pub const fn hand(&self) -> &Hand {
+        &self.hand
+    }
+
+    pub fn king_square(&self, color: Color) -> Option<usize> {
+        self.pieces(PieceType::King, color).squares().next()
+    }
+
+    fn put_piece(&mut self, sq: usize, piece: Piece) {
+        let t = tables();
+        self.squares[sq] = Some(piece);
+        self.pieces[piece.color as usize][piece.piece_type as usize - 1] |=
+            Bitboard::from_square(sq);
+        self.colors[piece.color as usize] |= Bitboard::from_square(sq);
+        for direction in 0..4 {
+            self.rotated[direction] |= 1 << t.rotated_index[direction][sq];
+        }
+        self.key ^= piece_key(piece, sq);
+    }
+
+    fn remove_piece(&mut self, sq: usize) -> Option<Piece> {
+        let t = tables();
+        let piece = self.squares[sq].take()?;
+        self.pieces[piece.color as usize][piece.piece_type as usize - 1] ^=
+            Bitboard::from_square(sq);
+        self.colors[piece.color as usize] ^= Bitboard::from_square(sq);
+        for direction in 0..4 {
+            self.rotated[direction] &= !(1 << t.rotated_index[direction][sq]);
+        }
+        self.key ^= piece_key(piece, sq);
+        Some(piece)
+    }
+
+    // 持ち駒を1枚増やす(addがtrue)か減らす(addがfalse)
+    fn update_hand(&mut self, piece: Piece, add: bool) {
+        let before = self.hand.count(piece.piece_type, piece.color) as usize;
+        let changed = if add {
+            self.hand.add(piece)
+        } else {
+            self.hand.remove(piece)
+        };
+        if changed {
+            let after = self.hand.count(piece.piece_type, piece.color) as usize;
+            self.key ^= hand_key(piece.piece_type, piece.color, before)
+                ^ hand_key(piece.piece_type, piece.color, after);
+        }
+    }
+
+    // 回転bitboardから線上の占有状態を取り出して利きを引く
+    fn line_attacks(&self, direction: usize, sq: usize) -> Bitboard {
+        let t = tables();
+        let len = t.line_len[direction][sq];
+        let occupied =
+            (self.rotated[direction] >> t.line_start[direction][sq]) as usize & ((1 << len) - 1);
+        t.lines[direction * SQUARE_COUNT + sq][occupied]
+    }
+
+    fn rook_attacks(&self, sq: usize) -> Bitboard {
+        self.line_attacks(RANK, sq) | self.line_attacks(FILE, sq)
+    }
+
+    fn bishop_attacks(&self, sq: usize) -> Bitboard {
+        self.line_attacks(DIAGONAL, sq) | self.line_attacks(ANTI_DIAGONAL, sq)
+    }
+
+    fn lance_attacks(&self, sq: usize, color: Color) -> Bitboard {
+        self.line_attacks(FILE, sq) & tables().forward[color as usize][sq]
+    }
+
+    // sqにある駒pieceの利き (味方の駒がある升も含む)
+    pub fn attacks_from(&self, piece: Piece, sq: usize) -> Bitboard {
+        let steps = tables().steps[piece.color as usize][piece.piece_type as usize - 1][sq];
+        match piece.piece_type {
+            PieceType::Rook => self.rook_attacks(sq),
+            PieceType::Bishop => self.bishop_attacks(sq),
+            PieceType::Dragon => self.rook_attacks(sq) | steps,
+            PieceType::Horse => self.bishop_attacks(sq) | steps,
+            PieceType::Lance => self.lance_attacks(sq, piece.color),
+            _ => steps,
+        }
+    }
+
+    // sqに利いているcolorの駒
+    pub fn attackers_to(&self, sq: usize, color: Color) -> Bitboard {
+        let t = tables();
+        // 相手の駒をsqに置いたときの利きの先に同じ種類の駒があれば利いている
+        let steps = |piece_type: PieceType| {
+            t.steps[color.opponent() as usize][piece_type as usize - 1][sq]
+                & self.pieces(piece_type, color)
+        };
+        let golds = [
+            PieceType::Gold,
+            PieceType::PromotedSilver,
+            PieceType::PromotedKnight,
+            PieceType::PromotedLance,
+            PieceType::PromotedPawn,
+        ]
+        .iter()
+        .fold(Bitboard::EMPTY, |b, &t| b | self.pieces(t, color));
+        let kings = self.pieces(PieceType::King, color)
+            | self.pieces(PieceType::Dragon, color)
+            | self.pieces(PieceType::Horse, color);
+        let rooks = self.pieces(PieceType::Rook, color) | self.pieces(PieceType::Dragon, color);
+        let bishops = self.pieces(PieceType::Bishop, color) | self.pieces(PieceType::Horse, color);
+
+        steps(PieceType::Pawn)
+            | steps(PieceType::Knight)
+            | steps(PieceType::Silver)
+            | (t.steps[color.opponent() as usize][PieceType::Gold as usize - 1][sq] & golds)
+            | (t.steps[color.opponent() as usize][PieceType::King as usize - 1][sq] & kings)
+            | (self.rook_attacks(sq) & rooks)
+            | (self.bishop_attacks(sq) & bishops)
+            | (self.lance_attacks(sq, color.opponent()) & self.pieces(PieceType::Lance, color))
+    }
+
+    // sqにcolorの駒の利きがあるかどうか
+    pub fn is_attacked(&self, sq: usize, color: Color) -> bool {
+        !self.attackers_to(sq, color).is_empty()
+    }
+
+    // colorの玉が王手されているかどうか
+    pub fn is_checked(&self, color: Color) -> bool {
+        self.king_square(color)
+            .is_some_and(|sq| self.is_attacked(sq, color.opponent()))
+    }
+
+    // 手番の側の指し手を生成する関数
+    // board::create_move_range と同じく自殺手・打ち歩詰めは含む
+    pub fn create_move_range(&self) -> Vec<Move> {
+        let own = self.colors[self.turn as usize];
+        let mut moves = vec![];
+        for from in own.squares() {
+            let piece = self.squares[from].unwrap();
+            self.push_normal_moves(from, self.attacks_from(piece, from) & !own, &mut moves);
+        }
+        self.push_drops(|_| !self.occupied(), &mut moves);
+        moves
+    }
+
+    // 手番の側の駒をtoの升に動かす手を生成する関数 (成る手・成らない手の両方、自殺手も含む)
+    pub fn moves_to(&self, to: usize) -> Vec<Move> {
+        let mut moves = vec![];
+        for from in self.attackers_to(to, self.turn).squares() {
+            self.push_normal_moves(from, Bitboard::from_square(to), &mut moves);
+        }
+        moves
+    }
+
+    // fromの駒をtargetsの升に動かす手を追加する (成る手・成らない手の両方)
+    fn push_normal_moves(&self, from: usize, targets: Bitboard, moves: &mut Vec<Move>) {
+        let turn = self.turn;
+        let piece = self.squares[from].unwrap();
+        for to in targets.squares() {
+            let (from_y, to_y) = (square_xy(from).1 as i32, square_xy(to).1 as i32);
+            if !is_dead_square(piece.piece_type, turn, to_y) {
+                moves.push(Move::Normal {
+                    from,
+                    to,
+                    promote: false,
+                });
+            }
+            if piece.can_revolte()
+                && (is_in_enemy_camp(from_y, turn) || is_in_enemy_camp(to_y, turn))
+            {
+                moves.push(Move::Normal {
+                    from,
+                    to,
+                    promote: true,
+                });
+            }
+        }
+    }
+
+    // 持ち駒をtargetsが返す升(空いている升)に打つ手を追加する
+    fn push_drops(&self, targets: impl Fn(PieceType) -> Bitboard, moves: &mut Vec<Move>) {
+        let t = tables();
+        let turn = self.turn;
+        for piece_type in HAND_PIECE_TYPES {
+            if self.hand.count(piece_type, turn) == 0 {
+                continue;
+            }
+            let mut targets = targets(piece_type);
+            for y in 0..BOARD_SIZE {
+                if is_dead_square(piece_type, turn, y as i32) {
+                    targets &= !t.ranks[y];
+                }
+            }
+            // 二歩になる筋には打てない
+            if piece_type == PieceType::Pawn {
+                let pawns = self.pieces(PieceType::Pawn, turn);
+                for file in t.files {
+                    if !(pawns & file).is_empty() {
+                        targets &= !file;
+                    }
+                }
+            }
+            moves.extend(targets.squares().map(|to| Move::Drop { piece_type, to }));
+        }
+    }
+
+    // kingの升の玉にピンされている駒と、その駒が動ける升 (玉との間とピンしている駒の升)
+    fn pinned_pieces(&self, king: usize) -> Vec<(usize, Bitboard)> {
+        self.line_blockers(king, self.turn.opponent(), self.turn)
+    }
+
+    // kingの升の玉とsniper_colorの飛び駒の間にあるblocker_colorの駒1枚と、玉との間とその飛び駒の升
+    // 玉の側の駒ならピンされている駒、飛び駒の側の駒なら動くと開き王手になる駒
+    fn line_blockers(
+        &self,
+        king: usize,
+        sniper_color: Color,
+        blocker_color: Color,
+    ) -> Vec<(usize, Bitboard)> {
+        let t = tables();
+        let king_color = sniper_color.opponent();
+        // 間に駒がなければ玉に利く飛び駒
+        let empty_line = |direction: usize| t.lines[direction * SQUARE_COUNT + king][0];
+        let snipers = ((empty_line(RANK) | empty_line(FILE))
+            & (self.pieces(PieceType::Rook, sniper_color)
+                | self.pieces(PieceType::Dragon, sniper_color)))
+            | ((empty_line(DIAGONAL) | empty_line(ANTI_DIAGONAL))
+                & (self.pieces(PieceType::Bishop, sniper_color)
+                    | self.pieces(PieceType::Horse, sniper_color)))
+            | (t.forward[king_color as usize][king] & self.pieces(PieceType::Lance, sniper_color));
+        snipers
+            .squares()
+            .filter_map(|sniper| {
+                let between = t.between[king * SQUARE_COUNT + sniper];
+                let blockers = between & self.occupied();
+                if blockers.count() != 1
+                    || (blockers & self.colors[blocker_color as usize]).is_empty()
+                {
+                    return None;
+                }
+                let blocker = blockers.squares().next()?;
+                Some((blocker, between | Bitboard::from_square(sniper)))
+            })
+            .collect()
+    }
+
+    // 王手になる合法手だけを生成する関数 (直接の王手・開き王手・打って王手)
+    // 動かした駒が玉に利く升か、開き王手になる駒の手だけを候補にしてから確かめる
+    pub fn check_moves(&self) -> Vec<Move> {
+        let turn = self.turn;
+        let them = turn.opponent();
+        let Some(king) = self.king_square(them) else {
+            return vec![];
+        };
+        let own = self.colors[turn as usize];
+        // 駒を置くと相手の玉に利く升 (玉の位置に相手の駒を置いたときの利きと同じ)
+        let check_squares =
+            |piece_type: PieceType| self.attacks_from(Piece::new(piece_type, them), king);
+        let discoverers = self.line_blockers(king, turn, turn);
+
+        let mut candidates = vec![];
+        for from in own.squares() {
+            let piece = self.squares[from].unwrap();
+            let targets = if discoverers.iter().any(|(sq, _)| *sq == from) {
+                Bitboard::ALL
+            } else if piece.can_revolte() {
+                check_squares(piece.piece_type) | check_squares(piece.revolute().piece_type)
+            } else {
+                check_squares(piece.piece_type)
+            };
+            let targets = targets & self.attacks_from(piece, from) & !own;
+            self.push_normal_moves(from, targets, &mut candidates);
+        }
+        let empty = !self.occupied();
+        self.push_drops(
+            |piece_type| check_squares(piece_type) & empty,
+            &mut candidates,
+        );
+
+        candidates.retain(|m| {
+            let next = self.move_piece(m);
+            next.is_checked(them) && !next.is_checked(turn) && !self.is_uchifuzume(m)
+        });
+        candidates
+    }
+
+    // 自殺手・打ち歩詰めを除いた指し手を生成する関数
+    // 王手している駒とピンされている駒を先に求めて、合法な手だけを生成する
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let t = tables();
+        let turn = self.turn;
+        let own = self.colors[turn as usize];
+        let Some(king) = self.king_square(turn) else {
+            let mut moves = self.create_move_range();
+            moves.retain(|m| !self.is_uchifuzume(m));
+            return moves;
+        };
+        let mut moves = vec![];
+
+        // 玉は、玉を取り除いた局面で相手の利きがない升にだけ動ける
+        let mut without_king = *self;
+        without_king.remove_piece(king);
+        let king_targets = (self.attacks_from(self.squares[king].unwrap(), king) & !own)
+            .squares()
+            .filter(|&to| !without_king.is_attacked(to, turn.opponent()))
+            .fold(Bitboard::EMPTY, |b, to| b | Bitboard::from_square(to));
+        self.push_normal_moves(king, king_targets, &mut moves);
+
+        // 王手されている場合は王手している駒を取るか間に駒を入れる (両王手は玉を動かすしかない)
+        let checkers = self.attackers_to(king, turn.opponent());
+        let (targets, drop_targets) = match checkers.count() {
+            0 => (!own, !self.occupied()),
+            1 => {
+                let checker = checkers.squares().next().unwrap();
+                let between = t.between[king * SQUARE_COUNT + checker];
+                (between | checkers, between)
+            }
+            _ => return moves,
+        };
+        let pinned = self.pinned_pieces(king);
+        for from in (own ^ Bitboard::from_square(king)).squares() {
+            let piece = self.squares[from].unwrap();
+            let mut to = self.attacks_from(piece, from) & !own & targets;
+            if let Some((_, line)) = pinned.iter().find(|(sq, _)| *sq == from) {
+                to &= *line;
+            }
+            self.push_normal_moves(from, to, &mut moves);
+        }
+        self.push_drops(|_| drop_targets, &mut moves);
+        moves.retain(|m| !self.is_uchifuzume(m));
+        moves
+    }
+
+    // 歩を打って相手の玉を詰ませる手かどうか
+    fn is_uchifuzume(&self, m: &Move) -> bool {
+        let Move::Drop {
+            piece_type: PieceType::Pawn,
+            to,
+        } = *m
+        else {
+            return false;
+        };
+        let Some(king) = self.king_square(self.turn.opponent()) else {
+            return false;
+        };
+        let pawn_attacks = tables().steps[self.turn as usize][PieceType::Pawn as usize - 1][to];
+        pawn_attacks.contains(king) && self.move_piece(m).legal_moves().is_empty()
+    }
+
+    // 指し手を指した後の局面を返す関数 (手番も交代する)
+    pub fn move_piece(&self, m: &Move) -> Position {
+        let mut next = *self;
+        let turn = self.turn;
+        match *m {
+            Move::Normal { from, to, promote } => {
+                let piece = next
+                    .remove_piece(from)
+                    .expect("no piece at the move source");
+                if let Some(captured) = next.remove_piece(to) {
+                    let captured_type = captured.revolute_back().piece_type;
+                    next.update_hand(Piece::new(captured_type, turn), true);
+                }
+                next.put_piece(to, if promote { piece.revolute() } else { piece });
+            }
+            Move::Drop { piece_type, to } => {
+                next.update_hand(Piece::new(piece_type, turn), false);
+                next.put_piece(to, Piece::new(piece_type, turn));
+            }
+        }
+        next.turn = turn.opponent();
+        next.key ^= turn_key();
+        next
+    }
+
+    // 静的交換評価 (SEE)
+    // 指し手の移動先で駒の取り合いを最後まで続けたときに、指す側が得する駒の価値を返す
+    // 取り合いはお互いに一番安い駒から取り、損になるところで止める
+    // 駒を取り除くたびに利きを引き直すので、飛車・角・香の後ろの駒の利き(X線)も数える
+    // 移動元に駒がない手は0を返す
+    pub fn see(&self, m: &Move) -> i32 {
+        let to = m.to();
+        let to_y = square_xy(to).1 as i32;
+        let mut position = *self;
+        let (piece, promote) = match *m {
+            Move::Normal { from, promote, .. } => {
+                let Some(piece) = position.remove_piece(from) else {
+                    return 0;
+                };
+                (piece, promote)
+            }
+            Move::Drop { piece_type, .. } => (Piece::new(piece_type, self.turn), false),
+        };
+        let piece = if promote { piece.revolute() } else { piece };
+        // gains[i]はi手目の取り合いで取る側が得る価値から、それまでの得を引いたもの
+        let mut gains = vec![position
+            .remove_piece(to)
+            .map_or(0, |p| piece_value(p.piece_type))];
+        if promote {
+            gains[0] +=
+                piece_value(piece.piece_type) - piece_value(piece.revolute_back().piece_type);
+        }
+        position.put_piece(to, piece);
+
+        let mut side = self.turn.opponent();
+        loop {
+            let attackers = position.attackers_to(to, side);
+            let Some(from) = attackers
+                .squares()
+                .min_by_key(|&sq| piece_value(position.squares[sq].unwrap().piece_type))
+            else {
+                break;
+            };
+            let attacker = position.squares[from].unwrap();
+            // 相手の利きがある升へ玉で取ることはできない
+            if attacker.piece_type == PieceType::King && position.is_attacked(to, side.opponent()) {
+                break;
+            }
+            let captured = position.squares[to].unwrap();
+            let mut gain = piece_value(captured.piece_type);
+            let from_y = square_xy(from).1 as i32;
+            // 成れるときは成る
+            let attacker = if attacker.can_revolte()
+                && (is_in_enemy_camp(from_y, side) || is_in_enemy_camp(to_y, side))
+            {
+                gain +=
+                    piece_value(attacker.revolute().piece_type) - piece_value(attacker.piece_type);
+                attacker.revolute()
+            } else {
+                attacker
+            };
+            gains.push(gain - gains.last().unwrap());
+            position.remove_piece(from);
+            position.remove_piece(to);
+            position.put_piece(to, attacker);
+            side = side.opponent();
+        }
+        // 後ろから、取らずに止めた方が得な場合はそこで止めたことにする
+        while gains.len() > 1 {
+            let last = gains.pop().unwrap();
+            let prev = gains.last_mut().unwrap();
+            *prev = -(-*prev).max(last);
+        }
+        gains[0]
+    }
+}
+
+// その段に移動すると以後動けなくなる駒かどうか (歩・香は1段目、桂は2段目まで)
+pub(crate) fn is_dead_square(piece_type: PieceType, color: Color, y: i32) -> bool {
+    let rank = match color {
+        Color::Black => BOARD_SIZE as i32 - 1 - y,
+        Color::White => y,
+    };
+    match piece_type {
+        PieceType::Pawn | PieceType::Lance => rank == 0,
+        PieceType::Knight => rank <= 1,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sfen::{parse_sfen, to_sfen, INITIAL_SFEN},
+        usi::move_from_usi,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn position(sfen: &str) -> Position {
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        Position::from_boards(&boards, turn)
+    }
+
+    fn sorted(mut moves: Vec<Move>) -> Vec<Move> {
+        moves.sort_by_key(|m| match *m {
+            Move::Normal { from, to, promote } => (0, from, to, promote as usize),
+            Move::Drop { piece_type, to } => (1, piece_type as usize, to, 0),
+        });
+        moves
+    }
+
+    fn assert_check_moves(position: &Position) {
+        let them = position.turn().opponent();
+        let mut expected = position.legal_moves();
+        expected.retain(|m| position.move_piece(m).is_checked(them));
+        assert_eq!(
+            sorted(position.check_moves()),
+            sorted(expected),
+            "{}",
+            to_sfen(&position.to_boards(), position.turn(), 1)
+        );
+    }
+
+    #[test]
+    fn check_moves_match_filtered_legal_moves() {
+        let sfens = [
+            INITIAL_SFEN,
+            "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+            // 角と飛車の開き王手
+            "k8/9/2S6/3B5/9/9/9/G8/R8 b P 1",
+            // 香の開き王手と、香を打つ王手
+            "4k4/9/9/9/4S4/9/9/9/4L4 b L 1",
+            // 成って王手になる桂・歩・角
+            "4k4/9/3P5/3N1N3/9/9/B8/9/9 b - 1",
+            // 攻め方の玉がない詰将棋の局面
+            "7nl/7k1/6ppp/9/9/9/9/9/9 b RBGSNLP 1",
+        ];
+        let mut positions = sfens.iter().map(|sfen| position(sfen)).collect::<Vec<_>>();
+        // 初期局面からランダムに指した局面
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let mut p = position(INITIAL_SFEN);
+            for _ in 0..150 {
+                positions.push(p);
+                let moves = p.legal_moves();
+                if moves.is_empty() {
+                    break;
+                }
+                p = p.move_piece(&moves[rng.gen_range(0..moves.len())]);
+            }
+        }
+        for p in positions.iter() {
+            assert_check_moves(p);
+        }
+    }
+
+    // SFENの局面でUSIの手を指したときのSEE
+    fn see(sfen: &str, usi: &str) -> i32 {
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        let m = move_from_usi(&boards, turn, usi).unwrap();
+        Position::from_boards(&boards, turn).see(&Move::from_legal_move(&m))
+    }
+
+    #[test]
+    fn see_counts_rook_behind_rook() {
+        // 飛車で金を取り、銀で取り返されても後ろの飛車で取り返せる
+        let sfen = "8k/9/5s3/4g4/9/9/9/4R4/K3R4 b - 1";
+        let gold = piece_value(PieceType::Gold);
+        let silver = piece_value(PieceType::Silver);
+        let rook = piece_value(PieceType::Rook);
+        assert_eq!(see(sfen, "5h5d"), gold - rook + silver);
+        // 後ろの飛車がなければ取り返されて損をする
+        assert_eq!(see("8k/9/5s3/4g4/9/9/9/4R4/K8 b - 1", "5h5d"), gold - rook);
+    }
+
+    #[test]
+    fn see_defended_pawn() {
+        let pawn = piece_value(PieceType::Pawn);
+        let silver = piece_value(PieceType::Silver);
+        assert_eq!(
+            see("8k/9/4g4/4p4/4S4/9/9/9/K8 b - 1", "5e5d"),
+            pawn - silver
+        );
+        assert_eq!(see("8k/9/9/4p4/4S4/9/9/9/K8 b - 1", "5e5d"), pawn);
+    }
+
+    #[test]
+    fn see_counts_promotion_gain() {
+        let sfen = "8k/9/5p3/5S3/9/9/9/9/K8 b - 1";
+        let pawn = piece_value(PieceType::Pawn);
+        let promotion = piece_value(PieceType::PromotedSilver) - piece_value(PieceType::Silver);
+        assert_eq!(see(sfen, "4d4c"), pawn);
+        assert_eq!(see(sfen, "4d4c+"), pawn + promotion);
+    }
+
+    #[test]
+    fn see_king_does_not_recapture_into_attacked_square() {
+        let gold = piece_value(PieceType::Gold);
+        let rook = piece_value(PieceType::Rook);
+        // 5三の金が5二に利いているので玉で取り返せない
+        assert_eq!(see("4k4/R3g4/4G4/9/9/9/9/9/K8 b - 1", "9b5b"), gold);
+        // 利きがなければ玉で取り返される
+        assert_eq!(see("4k4/R3g4/9/9/9/9/9/9/K8 b - 1", "9b5b"), gold - rook);
+    }
+
+    #[test]
+    fn see_without_piece_at_source() {
+        let (boards, turn, _) = parse_sfen(INITIAL_SFEN).unwrap();
+        let m = Move::Normal {
+            from: square(4, 4),
+            to: square(4, 5),
+            promote: false,
+        };
+        assert_eq!(Position::from_boards(&boards, turn).see(&m), 0);
+        assert_eq!(crate::board::see(&boards, m.to_legal_move(turn)), 0);
+    }
+}
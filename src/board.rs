@@ -1,10 +1,26 @@
-use crate::piece::{concat_vec, Color, Piece, PieceType};
+use crate::{
+    bitboard,
+    piece::{concat_vec, Color, Piece, PieceType},
+    zobrist,
+};
 use rayon::prelude::*;
 use std::fmt;
 
 pub const BOARD_SIZE: usize = 9;
+// モデルの入力の面の組数 (盤上の駒と持ち駒)
 pub const PAGE_SIZE: usize = 2;
 
+// 持ち駒になる駒の種類 (棋譜に書く順番)
+pub const HAND_PIECE_TYPES: [PieceType; 7] = [
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Gold,
+    PieceType::Silver,
+    PieceType::Knight,
+    PieceType::Lance,
+    PieceType::Pawn,
+];
+
 // ボード上の座標を表す構造体
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Position {
@@ -35,12 +51,80 @@ impl Position {
 
 // ボード上の駒配置を表す2次元配列
 pub type Board = [[Option<Piece>; BOARD_SIZE]; BOARD_SIZE];
-// 持ち駒を含むボード全体を表す3次元配列
-pub type Boards = [Board; PAGE_SIZE];
+
+// 持ち駒の最大枚数 (HAND_PIECE_TYPESの順)
+pub(crate) const HAND_MAX_COUNTS: [u8; HAND_PIECE_TYPES.len()] = [2, 2, 4, 4, 4, 4, 18];
+
+// 持ち駒の種類からHAND_PIECE_TYPESでの位置を返す関数
+pub fn hand_index(piece_type: PieceType) -> Option<usize> {
+    HAND_PIECE_TYPES.iter().position(|&t| t == piece_type)
+}
+
+// 手番・駒の種類ごとの持ち駒の枚数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Hand {
+    counts: [[u8; HAND_PIECE_TYPES.len()]; 2],
+}
+
+impl Hand {
+    pub fn count(&self, piece_type: PieceType, color: Color) -> u8 {
+        hand_index(piece_type).map_or(0, |i| self.counts[color as usize][i])
+    }
+
+    // 持ち駒を1枚増やす
+    // 持ち駒にできない駒の場合や最大枚数を超える場合はfalseを返す
+    pub fn add(&mut self, piece: Piece) -> bool {
+        let Some(i) = hand_index(piece.piece_type) else {
+            return false;
+        };
+        let count = &mut self.counts[piece.color as usize][i];
+        if *count >= HAND_MAX_COUNTS[i] {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    // 持ち駒を1枚減らす
+    // 持っていない場合はfalseを返す
+    pub fn remove(&mut self, piece: Piece) -> bool {
+        let Some(i) = hand_index(piece.piece_type) else {
+            return false;
+        };
+        let count = &mut self.counts[piece.color as usize][i];
+        if *count == 0 {
+            return false;
+        }
+        *count -= 1;
+        true
+    }
+
+    pub fn is_empty(&self, color: Color) -> bool {
+        self.counts[color as usize].iter().all(|&c| c == 0)
+    }
+}
+
+// 盤面と持ち駒を合わせた局面
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Boards {
+    pub board: Board,
+    pub hand: Hand,
+}
+
+impl Boards {
+    // 駒のない局面
+    pub fn empty() -> Boards {
+        Boards {
+            board: [[None; BOARD_SIZE]; BOARD_SIZE],
+            hand: Hand::default(),
+        }
+    }
+}
 
 pub type BoardAsNum =
     [[[u8; PieceType::get_max() as usize * PAGE_SIZE * 2]; BOARD_SIZE]; BOARD_SIZE];
 
+// 持ち駒を打つ手はfrom.zが1で、from.xがHAND_PIECE_TYPESでの位置、from.yが手番になる
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct LegalMove {
     pub from: Position,
@@ -49,6 +133,40 @@ pub struct LegalMove {
 }
 
 impl LegalMove {
+    // 持ち駒を打つ手を作る
+    pub fn drop(piece: Piece, to: Position) -> LegalMove {
+        LegalMove {
+            from: Position::new(
+                hand_index(piece.piece_type).expect("piece can not be dropped") as i32,
+                piece.color as i32,
+                1,
+            ),
+            to,
+            revolute: false,
+        }
+    }
+
+    // 持ち駒を打つ手なら打つ駒を返す
+    pub fn drop_piece(&self) -> Option<Piece> {
+        if self.from.z != 1 {
+            return None;
+        }
+        let color = if self.from.y == Color::Black as i32 {
+            Color::Black
+        } else {
+            Color::White
+        };
+        Some(Piece::new(HAND_PIECE_TYPES[self.from.x as usize], color))
+    }
+
+    // 動かす駒(打つ手なら打つ駒)を返す
+    pub fn piece(&self, boards: &Boards) -> Option<Piece> {
+        match self.drop_piece() {
+            Some(piece) => Some(piece),
+            None => boards.board[self.from.y as usize][self.from.x as usize],
+        }
+    }
+
     // 成れるかどうかを判定する関数
     pub fn can_revolte(&self, turn: Color) -> bool {
         if self.from.z == 1 {
@@ -74,9 +192,115 @@ impl LegalMove {
 }
 
 pub fn create_initial_board() -> Boards {
-    let mut boards: Boards = [[[None; BOARD_SIZE]; BOARD_SIZE]; PAGE_SIZE];
-    boards[0] = create_initial_board_black(boards[0]);
-    boards[0] = create_initial_board_white(boards[0]);
+    let mut boards = Boards::empty();
+    boards.board = create_initial_board_black(boards.board);
+    boards.board = create_initial_board_white(boards.board);
+    boards
+}
+
+// 手合割 (駒落ちは上手の後手が駒を落とし、後手から指す)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handicap {
+    Even,
+    Lance,
+    RightLance,
+    Bishop,
+    Rook,
+    RookLance,
+    TwoPieces,
+    ThreePieces,
+    FourPieces,
+    FivePieces,
+    LeftFivePieces,
+    SixPieces,
+    EightPieces,
+    TenPieces,
+}
+
+impl Handicap {
+    pub const ALL: [Handicap; 14] = [
+        Handicap::Even,
+        Handicap::Lance,
+        Handicap::RightLance,
+        Handicap::Bishop,
+        Handicap::Rook,
+        Handicap::RookLance,
+        Handicap::TwoPieces,
+        Handicap::ThreePieces,
+        Handicap::FourPieces,
+        Handicap::FivePieces,
+        Handicap::LeftFivePieces,
+        Handicap::SixPieces,
+        Handicap::EightPieces,
+        Handicap::TenPieces,
+    ];
+
+    // KIFの手合割の名前
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Handicap::Even => "平手",
+            Handicap::Lance => "香落ち",
+            Handicap::RightLance => "右香落ち",
+            Handicap::Bishop => "角落ち",
+            Handicap::Rook => "飛車落ち",
+            Handicap::RookLance => "飛香落ち",
+            Handicap::TwoPieces => "二枚落ち",
+            Handicap::ThreePieces => "三枚落ち",
+            Handicap::FourPieces => "四枚落ち",
+            Handicap::FivePieces => "五枚落ち",
+            Handicap::LeftFivePieces => "左五枚落ち",
+            Handicap::SixPieces => "六枚落ち",
+            Handicap::EightPieces => "八枚落ち",
+            Handicap::TenPieces => "十枚落ち",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Handicap> {
+        Handicap::ALL.iter().find(|h| h.name() == name).copied()
+    }
+
+    // 後手の駒を取り除く升 (x, y)
+    pub const fn removed_squares(&self) -> &'static [(usize, usize)] {
+        // 1一香, 9一香, 2一桂, 8一桂, 2二角, 8二飛
+        const L1: (usize, usize) = (0, 8);
+        const L9: (usize, usize) = (8, 8);
+        const N2: (usize, usize) = (1, 8);
+        const N8: (usize, usize) = (7, 8);
+        const B: (usize, usize) = (1, 7);
+        const R: (usize, usize) = (7, 7);
+        match self {
+            Handicap::Even => &[],
+            Handicap::Lance => &[L1],
+            Handicap::RightLance => &[L9],
+            Handicap::Bishop => &[B],
+            Handicap::Rook => &[R],
+            Handicap::RookLance => &[R, L1],
+            Handicap::TwoPieces => &[R, B],
+            Handicap::ThreePieces => &[R, B, L1],
+            Handicap::FourPieces => &[R, B, L1, L9],
+            Handicap::FivePieces => &[R, B, L1, L9, N8],
+            Handicap::LeftFivePieces => &[R, B, L1, L9, N2],
+            Handicap::SixPieces => &[R, B, L1, L9, N2, N8],
+            Handicap::EightPieces => &[R, B, L1, L9, N2, N8, (2, 8), (6, 8)],
+            Handicap::TenPieces => &[R, B, L1, L9, N2, N8, (2, 8), (6, 8), (3, 8), (5, 8)],
+        }
+    }
+
+    // 最初に指す側
+    pub fn first_turn(&self) -> Color {
+        match self {
+            Handicap::Even => Color::Black,
+            _ => Color::White,
+        }
+    }
+}
+
+// 手合割の初期局面を作る関数
+pub fn create_handicap_board(handicap: Handicap) -> Boards {
+    let mut boards = create_initial_board();
+    for &(x, y) in handicap.removed_squares() {
+        boards.board[y][x] = None;
+    }
     boards
 }
 
@@ -138,7 +362,8 @@ fn create_initial_board_white(mut board: Board) -> Board {
 
 // ボード上の駒の移動範囲を生成する関数
 pub fn create_move_range(boards: &Boards, turn: Color) -> Vec<LegalMove> {
-    let mut move_ranges = boards[0]
+    let mut move_ranges = boards
+        .board
         .par_iter()
         .enumerate()
         .flat_map(|(y, row)| {
@@ -148,463 +373,196 @@ pub fn create_move_range(boards: &Boards, turn: Color) -> Vec<LegalMove> {
                 .flat_map(|(x, piece)| {
                     piece
                         .unwrap()
-                        .create_move_range(Position::new(x as i32, y as i32, 0), &boards[0])
+                        .create_move_range(Position::new(x as i32, y as i32, 0), &boards.board)
                 })
                 .collect::<Vec<_>>()
         })
         .collect::<Vec<_>>();
 
-    // 歩を打つ手を生成する関数
-    match turn {
-        Color::Black => {
-            'outer: for y in (0..2).rev() {
-                for x in (0..BOARD_SIZE).rev() {
-                    if let Some(put_piece) = boards[1][y][x] {
-                        let m_range = put_piece.create_put_range(
-                            Position {
-                                x: x as i32,
-                                y: y as i32,
-                                z: 1,
-                            },
-                            &boards[0],
-                        );
-
-                        move_ranges = concat_vec(move_ranges, m_range);
-                        break 'outer;
-                    }
-                }
-            }
-        }
-        Color::White => {
-            'outer: for y in BOARD_SIZE - 2..BOARD_SIZE {
-                for x in 0..BOARD_SIZE {
-                    if let Some(put_piece) = boards[1][y][x] {
-                        let m_range = put_piece.create_put_range(
-                            Position {
-                                x: x as i32,
-                                y: y as i32,
-                                z: 1,
-                            },
-                            &boards[0],
-                        );
-
-                        move_ranges = concat_vec(move_ranges, m_range);
-                        break 'outer;
-                    }
-                }
-            }
-        }
-    }
-
-    // 金を打つ手を生成する関数
-    match turn {
-        Color::Black => {
-            for x in (0..4).rev() {
-                if let Some(p) = boards[1][2][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 2,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
-        Color::White => {
-            for x in BOARD_SIZE - 4..BOARD_SIZE {
-                if let Some(p) = boards[1][6][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 6,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
+    // 持ち駒を打つ手を生成する
+    for (i, &piece_type) in HAND_PIECE_TYPES.iter().enumerate() {
+        if boards.hand.count(piece_type, turn) == 0 {
+            continue;
         }
+        let m_range = Piece::new(piece_type, turn)
+            .create_put_range(Position::new(i as i32, turn as i32, 1), &boards.board);
+        move_ranges = concat_vec(move_ranges, m_range);
     }
 
-    // 銀を打つ手を生成する関数
-    match turn {
-        Color::Black => {
-            for x in (4..8).rev() {
-                if let Some(p) = boards[1][2][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 2,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
-        Color::White => {
-            for x in BOARD_SIZE - 8..BOARD_SIZE - 4 {
-                if let Some(p) = boards[1][6][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 6,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
-    }
+    move_ranges
+}
 
-    // 桂を打つ手を生成する関数
-    match turn {
-        Color::Black => {
-            for x in (0..4).rev() {
-                if let Some(p) = boards[1][3][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 3,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
-        Color::White => {
-            for x in BOARD_SIZE - 4..BOARD_SIZE {
-                if let Some(p) = boards[1][5][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 5,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
-    }
+// 自殺手・打ち歩詰めを除いた合法手を生成する関数
+// 王手とピンを考慮して合法手だけを生成するbitboardの指し手生成を使う
+pub fn create_legal_moves(boards: &Boards, turn: Color) -> Vec<LegalMove> {
+    bitboard::Position::from_boards(boards, turn)
+        .legal_moves()
+        .iter()
+        .map(|m| m.to_legal_move(turn))
+        .collect()
+}
 
-    // 香を打つ手を生成する関数
-    match turn {
-        Color::Black => {
-            for x in (4..8).rev() {
-                if let Some(p) = boards[1][3][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 3,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
-        Color::White => {
-            for x in BOARD_SIZE - 8..BOARD_SIZE - 4 {
-                if let Some(p) = boards[1][5][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 5,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
-    }
+// 指し手の移動先での駒の取り合いの損得を返す関数 (静的交換評価)
+// 指す側は指し手の駒の手番の側 (移動元に駒がない手は0を返す)
+pub fn see(boards: &Boards, m: LegalMove) -> i32 {
+    let Some(piece) = m.piece(boards) else {
+        return 0;
+    };
+    let turn = piece.color;
+    bitboard::Position::from_boards(boards, turn).see(&bitboard::Move::from_legal_move(&m))
+}
 
-    // 角行を打つ手を生成する関数
-    match turn {
-        Color::Black => {
-            for x in (0..2).rev() {
-                if let Some(p) = boards[1][4][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 4,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
-        Color::White => {
-            for x in BOARD_SIZE - 2..BOARD_SIZE {
-                if let Some(p) = boards[1][4][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 4,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
-    }
+// 歩を打つ手かどうかを判定する関数 (駒台の位置ではなく駒の種類で判定する)
+pub fn is_pawn_drop(m: &LegalMove) -> bool {
+    matches!(m.drop_piece(), Some(p) if p.piece_type == PieceType::Pawn)
+}
 
-    // 飛車を打つ手を生成する関数
-    match turn {
-        Color::Black => {
-            for x in (2..4).rev() {
-                if let Some(p) = boards[1][4][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 4,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
-        Color::White => {
-            for x in BOARD_SIZE - 4..BOARD_SIZE - 2 {
-                if let Some(p) = boards[1][4][x] {
-                    let m_range = p.create_put_range(
-                        Position {
-                            x: x as i32,
-                            y: 4,
-                            z: 1,
-                        },
-                        &boards[0],
-                    );
-                    move_ranges = concat_vec(move_ranges, m_range);
-                    break;
-                }
-            }
-        }
+// 打ち歩詰めかどうかを判定する関数
+// 歩を打って王手をかけ、相手に王手を解除する手(玉で歩を取る手を含む)がない場合だけ反則になる
+pub fn is_uchifuzume(boards: &Boards, m: &LegalMove) -> bool {
+    let Some(piece) = m.drop_piece() else {
+        return false;
+    };
+    if piece.piece_type != PieceType::Pawn {
+        return false;
     }
+    let next = move_piece(*boards, *m);
+    is_checked(&next.board, piece.color.opponent()) && is_checkmate(&next, piece.color.opponent())
+}
 
-    move_ranges
+// 持ち駒の種類と打つ位置から打つ手を検索する関数
+// 打てない場合(持っていない・二歩など)はNoneを返す
+pub fn find_drop_move(
+    boards: &Boards,
+    turn: Color,
+    piece_type: PieceType,
+    to: Position,
+) -> Option<LegalMove> {
+    create_move_range(boards, turn)
+        .into_iter()
+        .find(|m| m.to == to && matches!(m.drop_piece(), Some(p) if p.piece_type == piece_type))
 }
 
 #[allow(unused)]
 pub fn get_piece_count(boards: &Boards) -> usize {
-    boards
+    let on_board = boards
+        .board
         .par_iter()
-        .flat_map(|board| {
-            board
-                .par_iter()
-                .flat_map(|row| row.par_iter().filter(|piece| piece.is_some()))
+        .flat_map(|row| row.par_iter().filter(|piece| piece.is_some()))
+        .count();
+    let in_hand = [Color::Black, Color::White]
+        .iter()
+        .flat_map(|&color| {
+            HAND_PIECE_TYPES
+                .iter()
+                .map(move |&t| boards.hand.count(t, color) as usize)
         })
-        .count()
+        .sum::<usize>();
+    on_board + in_hand
 }
 
 // 駒を動かしその結果を返す関数
 pub fn move_piece(mut boards: Boards, legal_move: LegalMove) -> Boards {
-    let current_piece = boards[0][legal_move.to.y as usize][legal_move.to.x as usize];
-    // 駒を移動する
-    // 成る場合は成る
+    do_move(&mut boards, legal_move);
+    boards
+}
 
-    boards[0][legal_move.to.y as usize][legal_move.to.x as usize] = if legal_move.revolute {
-        boards[legal_move.from.z as usize][legal_move.from.y as usize][legal_move.from.x as usize]
-            .unwrap()
-            .revolute()
-            .into()
-    } else {
-        boards[legal_move.from.z as usize][legal_move.from.y as usize][legal_move.from.x as usize]
-    };
-    // 移動元を空欄にする
-    boards[legal_move.from.z as usize][legal_move.from.y as usize][legal_move.from.x as usize] =
-        None;
-    if current_piece.is_none() {
-        return boards;
-    }
-    // 駒を取った場合の処理
-    let mut piece = current_piece.unwrap().revolute_back();
-    piece.color = piece.color.opponent();
-    match (piece.piece_type, piece.color) {
-        (PieceType::Pawn, Color::Black) => {
-            'outer: for y in 0..2 {
-                for x in 0..BOARD_SIZE {
-                    let p = boards[1][y][x];
-                    if p.is_none() {
-                        boards[1][y][x] = Some(piece);
-                        break 'outer;
-                    }
-                }
-            }
-        }
-        (PieceType::Gold, Color::Black) => {
-            for x in 0..4 {
-                let p = boards[1][2][x];
-                if p.is_none() {
-                    boards[1][2][x] = Some(piece);
-                    break;
-                }
-            }
-        }
-        (PieceType::Silver, Color::Black) => {
-            for x in 4..8 {
-                let p = boards[1][2][x];
-                if p.is_none() {
-                    boards[1][2][x] = Some(piece);
-                    break;
-                }
-            }
-        }
-        (PieceType::Knight, Color::Black) => {
-            for x in 0..4 {
-                let p = boards[1][3][x];
-                if p.is_none() {
-                    boards[1][3][x] = Some(piece);
-                    break;
-                }
-            }
-        }
-        (PieceType::Lance, Color::Black) => {
-            for x in 4..8 {
-                let p = boards[1][3][x];
-                if p.is_none() {
-                    boards[1][3][x] = Some(piece);
-                    break;
-                }
-            }
-        }
-        (PieceType::Bishop, Color::Black) => {
-            for x in 0..2 {
-                let p = boards[1][4][x];
-                if p.is_none() {
-                    boards[1][4][x] = Some(piece);
-                    break;
-                }
-            }
-        }
-        (PieceType::Rook, Color::Black) => {
-            for x in 2..4 {
-                let p = boards[1][4][x];
-                if p.is_none() {
-                    boards[1][4][x] = Some(piece);
-                    break;
-                }
-            }
-        }
-        (PieceType::Pawn, Color::White) => {
-            'outer: for y in (BOARD_SIZE - 2..BOARD_SIZE).rev() {
-                for x in (0..BOARD_SIZE).rev() {
-                    let p = boards[1][y][x];
-                    if p.is_none() {
-                        boards[1][y][x] = Some(piece);
-                        break 'outer;
-                    }
-                }
-            }
-        }
-        (PieceType::Gold, Color::White) => {
-            for x in (BOARD_SIZE - 4..BOARD_SIZE).rev() {
-                let p = boards[1][6][x];
-                if p.is_none() {
-                    boards[1][6][x] = Some(piece);
-                    break;
-                }
-            }
-        }
-        (PieceType::Silver, Color::White) => {
-            for x in (BOARD_SIZE - 8..BOARD_SIZE - 4).rev() {
-                let p = boards[1][6][x];
-                if p.is_none() {
-                    boards[1][6][x] = Some(piece);
-                    break;
-                }
-            }
-        }
-        (PieceType::Knight, Color::White) => {
-            for x in (BOARD_SIZE - 4..BOARD_SIZE).rev() {
-                let p = boards[1][5][x];
-                if p.is_none() {
-                    boards[1][5][x] = Some(piece);
-                    break;
-                }
-            }
-        }
-        (PieceType::Lance, Color::White) => {
-            for x in (BOARD_SIZE - 8..BOARD_SIZE - 4).rev() {
-                let p = boards[1][5][x];
-                if p.is_none() {
-                    boards[1][5][x] = Some(piece);
-                    break;
-                }
-            }
-        }
-        (PieceType::Bishop, Color::White) => {
-            for x in (BOARD_SIZE - 2..BOARD_SIZE).rev() {
-                let p = boards[1][4][x];
-                if p.is_none() {
-                    boards[1][4][x] = Some(piece);
-                    break;
-                }
-            }
+// 指し手を戻すための記録
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UndoMove {
+    pub legal_move: LegalMove,
+    // 動かした駒 (成る前の駒)
+    pub piece: Piece,
+    // 取った駒 (盤上にあったときの駒)
+    pub captured: Option<Piece>,
+    // 指す前と指した後の局面のZobristハッシュの差 (手番の分も含む)
+    // 指す前のハッシュとXORを取ると指した後のハッシュになる
+    pub key: u64,
+}
+
+// 局面をコピーせずに指し手を指す関数
+// 戻す場合は返り値をundo_moveに渡す (返り値にはハッシュの差分も入る)
+pub fn do_move(boards: &mut Boards, legal_move: LegalMove) -> UndoMove {
+    let to = legal_move.to;
+    let to_sq = bitboard::square(to.x as usize, to.y as usize);
+    let captured = boards.board[to.y as usize][to.x as usize];
+    let mut key = zobrist::turn_key();
+    // 駒を移動元から取り除く (打つ場合は持ち駒から減らす)
+    let piece = match legal_move.drop_piece() {
+        Some(piece) => {
+            key ^= hand_count_key(&boards.hand, piece);
+            boards.hand.remove(piece);
+            key ^= hand_count_key(&boards.hand, piece);
+            piece
         }
-        (PieceType::Rook, Color::White) => {
-            for x in (BOARD_SIZE - 4..BOARD_SIZE - 2).rev() {
-                let p = boards[1][4][x];
-                if p.is_none() {
-                    boards[1][4][x] = Some(piece);
-                    break;
-                }
-            }
+        None => {
+            let from = legal_move.from;
+            let piece = boards.board[from.y as usize][from.x as usize]
+                .take()
+                .unwrap();
+            key ^= zobrist::piece_key(piece, bitboard::square(from.x as usize, from.y as usize));
+            piece
         }
-        _ => {}
+    };
+    // 成る場合は成る
+    let moved = if legal_move.revolute {
+        piece.revolute()
+    } else {
+        piece
+    };
+    boards.board[to.y as usize][to.x as usize] = Some(moved);
+    key ^= zobrist::piece_key(moved, to_sq);
+    // 駒を取った場合は持ち駒にする
+    if let Some(captured) = captured {
+        key ^= zobrist::piece_key(captured, to_sq);
+        let mut hand_piece = captured.revolute_back();
+        hand_piece.color = hand_piece.color.opponent();
+        key ^= hand_count_key(&boards.hand, hand_piece);
+        boards.hand.add(hand_piece);
+        key ^= hand_count_key(&boards.hand, hand_piece);
+    }
+    UndoMove {
+        legal_move,
+        piece,
+        captured,
+        key,
+    }
+}
+
+// 持ち駒の今の枚数のハッシュ値
+fn hand_count_key(hand: &Hand, piece: Piece) -> u64 {
+    zobrist::hand_key(
+        piece.piece_type,
+        piece.color,
+        hand.count(piece.piece_type, piece.color) as usize,
+    )
+}
+
+// do_moveで指した手を戻す関数
+pub fn undo_move(boards: &mut Boards, undo: &UndoMove) {
+    let from = undo.legal_move.from;
+    let to = undo.legal_move.to;
+    if let Some(captured) = undo.captured {
+        let mut hand_piece = captured.revolute_back();
+        hand_piece.color = hand_piece.color.opponent();
+        boards.hand.remove(hand_piece);
+    }
+    boards.board[to.y as usize][to.x as usize] = undo.captured;
+    if undo.legal_move.drop_piece().is_some() {
+        boards.hand.add(undo.piece);
+    } else {
+        boards.board[from.y as usize][from.x as usize] = Some(undo.piece);
     }
-    boards
 }
 
 pub fn print_boards(boards: &Boards) {
     let mut board_data = format!(
-        "{:->92}\n|{:>19}\x1b[31m先  手\x1b[m{:>19}|\n{:->92}\n",
+        "{:->46}\n|{:>17}\x1b[31m先  手\x1b[m{:>17}|\n{:->46}\n",
         "", "", "", ""
     );
 
     for y in 0..BOARD_SIZE {
-        boards[0][y].iter().for_each(|p| match p {
-            None => board_data.push_str(&format!("|{: >4}", "")),
-            Some(piece) => {
-                if piece.color == Color::Black {
-                    board_data.push_str(&format!("| \x1b[31m{}\x1b[m ", piece))
-                } else {
-                    board_data.push_str(&format!("| {} ", piece))
-                }
-            }
-        });
-        board_data.push_str("|");
-        boards[1][y].iter().for_each(|p| match p {
+        boards.board[y].iter().for_each(|p| match p {
             None => board_data.push_str(&format!("|{: >4}", "")),
             Some(piece) => {
                 if piece.color == Color::Black {
@@ -616,9 +574,22 @@ pub fn print_boards(boards: &Boards) {
         });
         board_data.push_str("|\n");
     }
-    board_data.push_str(&format!("{:->92}\n|{:>19}後  手{:>19}|\n", "", "", "",));
-    board_data.push_str(&format!("{:->92}", "",));
+    board_data.push_str(&format!("{:->46}\n|{:>17}後  手{:>17}|\n", "", "", "",));
+    board_data.push_str(&format!("{:->46}", "",));
     println!("{}", board_data);
+    // 持ち駒
+    for (color, name) in [(Color::Black, "\x1b[31m先手\x1b[m"), (Color::White, "後手")] {
+        let hand = HAND_PIECE_TYPES
+            .iter()
+            .filter(|&&t| boards.hand.count(t, color) > 0)
+            .map(|&t| format!("{}{}", Piece::new(t, color), boards.hand.count(t, color)))
+            .collect::<Vec<_>>();
+        if hand.is_empty() {
+            println!("{} 持ち駒: なし", name);
+        } else {
+            println!("{} 持ち駒: {}", name, hand.join(" "));
+        }
+    }
     let piece_count = get_piece_count(boards);
     println!("piece count: {:?}", piece_count);
 }
@@ -635,65 +606,114 @@ pub fn is_nifu(board: &Board, m: LegalMove, color: Color) -> bool {
     false
 }
 
+// 局面をモデルの入力の形式に変換する関数
+// 前半の28面は盤上の駒の位置、後半の28面は持ち駒の枚数で、枚数分の升を先頭から1にする
 pub fn get_num_array(boards: &Boards) -> BoardAsNum {
     let mut b: BoardAsNum =
         [[[0; PieceType::get_max() as usize * PAGE_SIZE * 2]; BOARD_SIZE]; BOARD_SIZE];
-    boards.iter().enumerate().for_each(|(z, board)| {
-        board.iter().enumerate().for_each(|(y, row)| {
-            row.iter().enumerate().for_each(|(x, p)| {
-                if let Some(piece) = p {
-                    let z_index = (piece.get_u8() as usize
-                        + match piece.color {
-                            Color::Black => 0,
-                            Color::White => PieceType::get_max() as usize,
-                        })
-                        * match z {
-                            0 => 1,
-                            _ => 2,
-                        }
-                        - 1;
-                    b[x][y][z_index] = 1;
-                }
-            });
+    let channel = |piece: &Piece| {
+        piece.get_u8() as usize
+            + match piece.color {
+                Color::Black => 0,
+                Color::White => PieceType::get_max() as usize,
+            }
+            - 1
+    };
+    boards.board.iter().enumerate().for_each(|(y, row)| {
+        row.iter().enumerate().for_each(|(x, p)| {
+            if let Some(piece) = p {
+                b[x][y][channel(piece)] = 1;
+            }
         });
     });
+    for color in [Color::Black, Color::White] {
+        for piece_type in HAND_PIECE_TYPES {
+            let z_index =
+                PieceType::get_max() as usize * 2 + channel(&Piece::new(piece_type, color));
+            for i in 0..boards.hand.count(piece_type, color) as usize {
+                b[i % BOARD_SIZE][i / BOARD_SIZE][z_index] = 1;
+            }
+        }
+    }
     b
 }
 
+// 盤面をコピーせずに1手ずつ指して戻し、王手が解除できる手があるかを調べる
 pub fn is_checkmate(boards: &Boards, turn: Color) -> bool {
-    create_move_range(boards, turn)
-        .par_iter()
-        .filter_map(|range| {
-            let boards = move_piece(*boards, *range);
-            let checked = is_checked(&boards[0], turn);
-            if checked {
-                None
-            } else {
-                Some(boards)
-            }
-        })
-        .count()
-        == 0
+    let mut boards = *boards;
+    create_move_range(&boards, turn).iter().all(|range| {
+        let undo = do_move(&mut boards, *range);
+        let checked = is_checked(&boards.board, turn);
+        undo_move(&mut boards, &undo);
+        checked
+    })
 }
 
 pub fn is_checked(board: &Board, color: Color) -> bool {
     // 王の位置を検索
     let king_position = find_king_position(board, color);
+    let boards = Boards {
+        board: *board,
+        ..Boards::empty()
+    };
+    !attackers_to(&boards, king_position, color.opponent()).is_empty()
+}
 
-    board.par_iter().enumerate().any(|(y, row)| {
-        row.par_iter().enumerate().any(|(x, piece)| {
-            if let Some(piece) = piece {
-                if piece.color != color {
-                    return piece.can_capture_king(
-                        Position::new(x as i32, y as i32, 0),
-                        board,
-                        king_position,
-                    );
-                }
+// 升ごとの利きの数 [手番][y][x]
+pub type AttackMap = [[[u8; BOARD_SIZE]; BOARD_SIZE]; 2];
+
+fn to_position(sq: usize) -> Position {
+    let (x, y) = bitboard::square_xy(sq);
+    Position::new(x as i32, y as i32, 0)
+}
+
+// squareに利いているcolorの駒の位置を返す関数
+pub fn attackers_to(boards: &Boards, square: Position, color: Color) -> Vec<Position> {
+    let sq = bitboard::square(square.x as usize, square.y as usize);
+    bitboard::Position::from_boards(boards, color)
+        .attackers_to(sq, color)
+        .squares()
+        .map(to_position)
+        .collect()
+}
+
+// 先手・後手それぞれの駒が各升に利いている数を数える関数
+pub fn attack_map(boards: &Boards) -> AttackMap {
+    let position = bitboard::Position::from_boards(boards, Color::Black);
+    let mut map = [[[0; BOARD_SIZE]; BOARD_SIZE]; 2];
+    for (y, row) in boards.board.iter().enumerate() {
+        for (x, piece) in row.iter().enumerate() {
+            let Some(piece) = piece else { continue };
+            for sq in position
+                .attacks_from(*piece, bitboard::square(x, y))
+                .squares()
+            {
+                let (ax, ay) = bitboard::square_xy(sq);
+                map[piece.color as usize][ay][ax] += 1;
             }
-            false
+        }
+    }
+    map
+}
+
+// colorの駒(玉以外)のうち、相手に取られると損をする駒の位置を返す関数
+// 相手の駒で取る手(成れるときは成る手も)のうち、駒の取り合いの損得が一番良い手で相手が得をする駒を数える
+pub fn threatened_pieces(boards: &Boards, color: Color) -> Vec<Position> {
+    let position = bitboard::Position::from_boards(boards, color.opponent());
+    position
+        .color_pieces(color)
+        .squares()
+        .filter(|&to| {
+            position.piece_on(to).unwrap().piece_type != PieceType::King
+                && position
+                    .moves_to(to)
+                    .iter()
+                    .map(|m| position.see(m))
+                    .max()
+                    .is_some_and(|gain| gain > 0)
         })
-    })
+        .map(to_position)
+        .collect()
 }
 
 // 王の位置を検索するヘルパー関数
@@ -710,3 +730,381 @@ fn find_king_position(board: &Board, color: Color) -> Position {
 
     panic!("King not found on the board!");
 }
+
+// 入玉宣言法の点数のルール
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImpasseRule {
+    // 24点法: 31点以上で宣言勝ち、24点以上30点以下で持将棋(引き分け)
+    Point24,
+    // 27点法: 先手は28点以上、後手は27点以上で宣言勝ち
+    Point27,
+}
+
+// 入玉宣言の結果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Declaration {
+    Win,
+    Draw,
+}
+
+// 敵陣(相手側の三段)の段かどうかを判定する関数
+pub fn is_in_enemy_camp(y: i32, color: Color) -> bool {
+    match color {
+        Color::Black => y >= BOARD_SIZE as i32 - 3,
+        Color::White => y < 3,
+    }
+}
+
+// 入玉宣言の点数と敵陣にある玉以外の駒の枚数を返す関数
+// 敵陣の駒と持ち駒が対象で、大駒(飛・角・龍・馬)は5点、それ以外は1点
+pub fn impasse_points(boards: &Boards, color: Color) -> (u32, usize) {
+    let camp = boards
+        .board
+        .iter()
+        .enumerate()
+        .filter(|(y, _)| is_in_enemy_camp(*y as i32, color))
+        .flat_map(|(_, row)| row.iter().flatten())
+        .filter(|p| p.color == color && p.piece_type != PieceType::King)
+        .collect::<Vec<_>>();
+    let point = |piece_type: PieceType| match piece_type {
+        PieceType::Rook | PieceType::Bishop => 5,
+        _ => 1,
+    };
+    let points = camp
+        .iter()
+        .map(|p| point(p.revolute_back().piece_type))
+        .chain(
+            HAND_PIECE_TYPES
+                .iter()
+                .map(|&t| point(t) * boards.hand.count(t, color) as u32),
+        )
+        .sum();
+    (points, camp.len())
+}
+
+// 手番の側が入玉宣言できるかを判定する関数
+// 玉が敵陣にいて王手されておらず、敵陣に玉以外の駒が10枚以上あり、点数が足りていること
+pub fn declare_entering_king(
+    boards: &Boards,
+    turn: Color,
+    rule: ImpasseRule,
+) -> Option<Declaration> {
+    let king_position = find_king_position(&boards.board, turn);
+    if !is_in_enemy_camp(king_position.y, turn) || is_checked(&boards.board, turn) {
+        return None;
+    }
+    let (points, count) = impasse_points(boards, turn);
+    if count < 10 {
+        return None;
+    }
+    let required = match (rule, turn) {
+        (ImpasseRule::Point24, _) => 31,
+        (ImpasseRule::Point27, Color::Black) => 28,
+        (ImpasseRule::Point27, Color::White) => 27,
+    };
+    if points >= required {
+        Some(Declaration::Win)
+    } else if rule == ImpasseRule::Point24 && points >= 24 {
+        Some(Declaration::Draw)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sfen::{parse_sfen, to_sfen, INITIAL_SFEN},
+        usi::{move_from_usi, move_to_usi},
+    };
+
+    // SFENの局面で先手が指すUSIの手と、指した後の局面
+    fn play(sfen: &str, usi: &str) -> (Boards, LegalMove) {
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        assert_eq!(turn, Color::Black);
+        let m = move_from_usi(&boards, turn, usi).unwrap();
+        (boards, m)
+    }
+
+    fn is_legal(boards: &Boards, m: &LegalMove) -> bool {
+        create_legal_moves(boards, Color::Black).contains(m)
+    }
+
+    #[test]
+    fn pawn_drop_is_detected_by_piece_type() {
+        let sfen = "8k/9/9/9/9/9/9/9/K8 b GP 1";
+        assert!(is_pawn_drop(&play(sfen, "P*5e").1));
+        assert!(!is_pawn_drop(&play(sfen, "G*5e").1));
+        assert!(!is_pawn_drop(&play(sfen, "9i9h").1));
+    }
+
+    #[test]
+    fn uchifuzume_when_pawn_is_defended() {
+        // 1二の歩は金が守っていて玉で取れず、2一・2二は自分の駒でふさがっている
+        let (boards, m) = play("7lk/7p1/8G/9/9/9/9/9/K8 b P 1", "P*1b");
+        assert!(is_uchifuzume(&boards, &m));
+        assert!(!is_legal(&boards, &m));
+    }
+
+    #[test]
+    fn not_uchifuzume_when_king_captures_pawn() {
+        let (boards, m) = play("7lk/7p1/9/9/9/9/9/9/K8 b P 1", "P*1b");
+        assert!(!is_uchifuzume(&boards, &m));
+        assert!(is_legal(&boards, &m));
+    }
+
+    #[test]
+    fn not_uchifuzume_when_other_piece_captures_pawn() {
+        // 玉では取れないが2二の金で取れる
+        let (boards, m) = play("7lk/7g1/8G/9/9/9/9/9/K8 b P 1", "P*1b");
+        assert!(!is_uchifuzume(&boards, &m));
+        assert!(is_legal(&boards, &m));
+    }
+
+    #[test]
+    fn not_uchifuzume_when_king_escapes() {
+        // 玉が2一に逃げられる
+        let (boards, m) = play("8k/7p1/8G/9/9/9/9/9/K8 b P 1", "P*1b");
+        assert!(!is_uchifuzume(&boards, &m));
+        assert!(is_legal(&boards, &m));
+    }
+
+    #[test]
+    fn pawn_push_mate_is_legal() {
+        // 盤上の歩を突いて詰ませるのは打ち歩詰めではない
+        let (boards, m) = play("7lk/7p1/7GP/9/9/9/9/9/K8 b - 1", "1c1b");
+        assert!(!is_uchifuzume(&boards, &m));
+        assert!(is_legal(&boards, &m));
+        assert!(is_checkmate(&move_piece(boards, m), Color::White));
+    }
+
+    // 駒の動きで指せる手から自殺手と打ち歩詰めを除く、bitboard以前の合法手生成
+    fn reference_legal_moves(boards: &Boards, turn: Color) -> Vec<LegalMove> {
+        create_move_range(boards, turn)
+            .into_iter()
+            .filter(|m| {
+                !is_checked(&move_piece(*boards, *m).board, turn) && !is_uchifuzume(boards, m)
+            })
+            .collect()
+    }
+
+    fn sorted_usi(moves: &[LegalMove]) -> Vec<String> {
+        let mut moves = moves.iter().map(move_to_usi).collect::<Vec<_>>();
+        moves.sort();
+        moves
+    }
+
+    fn assert_same_moves(boards: &Boards, turn: Color) {
+        assert_eq!(
+            sorted_usi(&create_legal_moves(boards, turn)),
+            sorted_usi(&reference_legal_moves(boards, turn)),
+            "{}",
+            to_sfen(boards, turn, 1)
+        );
+    }
+
+    #[test]
+    fn undo_move_restores_boards() {
+        // 駒を取る手・成る手・打つ手を含む局面で、指して戻すと元の局面になる
+        let sfens = [
+            INITIAL_SFEN,
+            "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+        ];
+        for sfen in sfens {
+            let (boards, turn, _) = parse_sfen(sfen).unwrap();
+            for m in create_legal_moves(&boards, turn) {
+                let mut next = boards;
+                let undo = do_move(&mut next, m);
+                assert_eq!(next, move_piece(boards, m));
+                for m2 in create_move_range(&next, turn.opponent()) {
+                    let mut after = next;
+                    let undo2 = do_move(&mut after, m2);
+                    undo_move(&mut after, &undo2);
+                    assert_eq!(after, next, "{} {}", move_to_usi(&m), move_to_usi(&m2));
+                }
+                undo_move(&mut next, &undo);
+                assert_eq!(next, boards, "{}", move_to_usi(&m));
+            }
+        }
+    }
+
+    #[test]
+    fn legal_moves_match_reference_generator() {
+        let sfens = [
+            INITIAL_SFEN,
+            "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+            // 飛車と角にピンされた駒
+            "k3r4/9/9/9/8b/9/9/4GS3/4K4 b - 1",
+            // 飛車と角の両王手 (玉を動かす手だけ)
+            "k3r4/9/9/9/8b/9/9/9/4K4 b G 1",
+            // 香の王手は合い駒を打てる
+            "4k4/9/9/9/4l4/9/9/9/4K4 b GP 1",
+            // 桂の王手は合い駒できない
+            "4k4/9/9/9/9/9/3n5/9/4K4 b GP 1",
+            // 打ち歩詰め
+            "7lk/7p1/8G/9/9/9/9/9/K8 b P 1",
+            // 成りと不成・行き所のない駒
+            "4k4/1P7/9/1L4N2/9/9/9/9/4K4 b NLP 1",
+        ];
+        for sfen in sfens {
+            let (boards, turn, _) = parse_sfen(sfen).unwrap();
+            assert_same_moves(&boards, turn);
+            // 1手・2手先の局面も比べる
+            for m in create_legal_moves(&boards, turn) {
+                let next = move_piece(boards, m);
+                assert_same_moves(&next, turn.opponent());
+                if sfen == INITIAL_SFEN {
+                    for m in create_legal_moves(&next, turn.opponent()) {
+                        assert_same_moves(&move_piece(next, m), turn);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn threatened_by_forced_promotion() {
+        // 5八の歩は5九の金を成って取るしかない
+        let (boards, _, _) = parse_sfen("8k/9/9/9/9/9/9/4p4/K3G4 b - 1").unwrap();
+        let position = bitboard::Position::from_boards(&boards, Color::White);
+        let to = bitboard::square(4, 0);
+        assert_eq!(
+            position.moves_to(to),
+            vec![bitboard::Move::Normal {
+                from: bitboard::square(4, 1),
+                to,
+                promote: true,
+            }]
+        );
+        assert_eq!(
+            threatened_pieces(&boards, Color::Black),
+            vec![Position::new(4, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn defended_piece_is_not_threatened() {
+        // 5七の歩を銀で取っても金で取り返される (成って取っても同じ)
+        let (boards, _, _) = parse_sfen("8k/9/9/9/9/4s4/4P4/4G4/K8 b - 1").unwrap();
+        assert!(threatened_pieces(&boards, Color::Black).is_empty());
+    }
+
+    // 駒落ちの標準的なSFEN (上手の後手から指す)
+    const HANDICAP_SFENS: [(Handicap, &str); 14] = [
+        (Handicap::Even, INITIAL_SFEN),
+        (
+            Handicap::Lance,
+            "lnsgkgsn1/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::RightLance,
+            "1nsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::Bishop,
+            "lnsgkgsnl/1r7/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::Rook,
+            "lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::RookLance,
+            "lnsgkgsn1/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::TwoPieces,
+            "lnsgkgsnl/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::ThreePieces,
+            "lnsgkgsn1/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::FourPieces,
+            "1nsgkgsn1/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::FivePieces,
+            "2sgkgsn1/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::LeftFivePieces,
+            "1nsgkgs2/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::SixPieces,
+            "2sgkgs2/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::EightPieces,
+            "3gkg3/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+        (
+            Handicap::TenPieces,
+            "4k4/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        ),
+    ];
+
+    #[test]
+    fn handicap_boards_match_standard_sfens() {
+        assert_eq!(HANDICAP_SFENS.len(), Handicap::ALL.len());
+        for (handicap, sfen) in HANDICAP_SFENS {
+            let boards = create_handicap_board(handicap);
+            assert_eq!(
+                to_sfen(&boards, handicap.first_turn(), 1),
+                sfen,
+                "{:?}",
+                handicap
+            );
+            assert_eq!(Handicap::from_name(handicap.name()), Some(handicap));
+        }
+    }
+
+    #[test]
+    fn handicap_removes_only_white_pieces() {
+        let initial = create_initial_board();
+        for handicap in Handicap::ALL {
+            let boards = create_handicap_board(handicap);
+            let removed = handicap.removed_squares();
+            for y in 0..BOARD_SIZE {
+                for x in 0..BOARD_SIZE {
+                    if removed.contains(&(x, y)) {
+                        let piece = initial.board[y][x].unwrap();
+                        assert_eq!(piece.color, Color::White, "{:?}", handicap);
+                        assert_ne!(piece.piece_type, PieceType::King);
+                        assert_eq!(boards.board[y][x], None);
+                    } else {
+                        assert_eq!(boards.board[y][x], initial.board[y][x]);
+                    }
+                }
+            }
+            assert_eq!(boards.hand, initial.hand);
+        }
+    }
+
+    #[test]
+    fn handicap_is_played_by_white_first() {
+        assert_eq!(Handicap::Even.first_turn(), Color::Black);
+        assert!(Handicap::Even.removed_squares().is_empty());
+        for handicap in &Handicap::ALL[1..] {
+            assert_eq!(handicap.first_turn(), Color::White, "{:?}", handicap);
+        }
+        // 取り除く駒の種類と枚数
+        let count = |handicap: Handicap, piece_type: PieceType| {
+            let initial = create_initial_board();
+            handicap
+                .removed_squares()
+                .iter()
+                .filter(|&&(x, y)| initial.board[y][x].unwrap().piece_type == piece_type)
+                .count()
+        };
+        assert_eq!(count(Handicap::RookLance, PieceType::Rook), 1);
+        assert_eq!(count(Handicap::RookLance, PieceType::Lance), 1);
+        assert_eq!(count(Handicap::SixPieces, PieceType::Knight), 2);
+        assert_eq!(count(Handicap::EightPieces, PieceType::Silver), 2);
+        assert_eq!(count(Handicap::TenPieces, PieceType::Gold), 2);
+        assert_eq!(Handicap::TenPieces.removed_squares().len(), 10);
+    }
+}
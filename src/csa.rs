@@ -0,0 +1,558 @@
+use crate::{
+    board::{
+        create_handicap_board, create_initial_board, create_move_range, find_drop_move, move_piece,
+        Boards, Handicap, LegalMove, Position, BOARD_SIZE, HAND_PIECE_TYPES,
+    },
+    piece::{Color, Piece, PieceType},
+    record::{GameRecord, Termination},
+};
+use anyhow::{anyhow, bail, Result};
+
+// CSA形式の棋譜の読み書き
+
+const CSA_VERSION: &str = "V2.2";
+
+const PIECE_CODES: [(&str, PieceType); 14] = [
+    ("OU", PieceType::King),
+    ("HI", PieceType::Rook),
+    ("KA", PieceType::Bishop),
+    ("KI", PieceType::Gold),
+    ("GI", PieceType::Silver),
+    ("KE", PieceType::Knight),
+    ("KY", PieceType::Lance),
+    ("FU", PieceType::Pawn),
+    ("RY", PieceType::Dragon),
+    ("UM", PieceType::Horse),
+    ("NG", PieceType::PromotedSilver),
+    ("NK", PieceType::PromotedKnight),
+    ("NY", PieceType::PromotedLance),
+    ("TO", PieceType::PromotedPawn),
+];
+
+// CSAの対局情報とKIFのヘッダの対応
+const HEADER_KEYS: [(&str, &str); 7] = [
+    ("N+", "先手"),
+    ("N-", "後手"),
+    ("$EVENT:", "棋戦"),
+    ("$SITE:", "場所"),
+    ("$START_TIME:", "開始日時"),
+    ("$END_TIME:", "終了日時"),
+    ("$OPENING:", "戦型"),
+];
+
+const TERMINATIONS: [(&str, Termination); 8] = [
+    ("%TORYO", Termination::Resign),
+    ("%TSUMI", Termination::Checkmate),
+    ("%TIME_UP", Termination::TimeUp),
+    ("%SENNICHITE", Termination::Sennichite),
+    ("%JISHOGI", Termination::Jishogi),
+    ("%KACHI", Termination::EnteringKing),
+    // 手番側の反則負け
+    ("%ILLEGAL_MOVE", Termination::IllegalLoss),
+    ("%CHUDAN", Termination::Interrupt),
+];
+
+// 棋譜をCSA形式の文字列に変換する関数
+pub fn to_csa(record: &GameRecord) -> String {
+    let mut csa = format!("{}\n", CSA_VERSION);
+    for (key, value) in &record.headers {
+        match HEADER_KEYS.iter().find(|(_, k)| k == key) {
+            Some((csa_key, _)) => csa.push_str(&format!("{}{}\n", csa_key, value)),
+            None if key.starts_with('$') => csa.push_str(&format!("{}:{}\n", key, value)),
+            None => csa.push_str(&format!("'{}:{}\n", key, value)),
+        }
+    }
+
+    // 平手・駒落ちの初期局面はPIと取り除く駒で書く
+    let handicap = Handicap::ALL
+        .iter()
+        .find(|h| record.initial_boards == create_handicap_board(**h));
+    let initial = create_initial_board();
+    if let Some(handicap) = handicap {
+        csa.push_str("PI");
+        for &(x, y) in handicap.removed_squares() {
+            let piece = initial.board[y][x].unwrap();
+            csa.push_str(&format!(
+                "{}{}",
+                square_to_csa(Position::new(x as i32, y as i32, 0)),
+                piece_code(piece.piece_type)
+            ));
+        }
+        csa.push('\n');
+    } else {
+        for y in (0..BOARD_SIZE).rev() {
+            csa.push_str(&format!("P{}", BOARD_SIZE - y));
+            for x in (0..BOARD_SIZE).rev() {
+                match record.initial_boards.board[y][x] {
+                    Some(piece) => csa.push_str(&piece_to_csa(&piece)),
+                    None => csa.push_str(" * "),
+                }
+            }
+            csa.push('\n');
+        }
+        for color in [Color::Black, Color::White] {
+            let hand = HAND_PIECE_TYPES
+                .iter()
+                .map(|&piece_type| {
+                    let count = record.initial_boards.hand.count(piece_type, color);
+                    format!("00{}", piece_code(piece_type)).repeat(count as usize)
+                })
+                .collect::<String>();
+            if !hand.is_empty() {
+                csa.push_str(&format!("P{}{}\n", color_sign(color), hand));
+            }
+        }
+    }
+    csa.push_str(&format!("{}\n", color_sign(record.initial_turn)));
+
+    let mut boards = record.initial_boards;
+    let mut turn = record.initial_turn;
+    for (i, m) in record.moves.iter().enumerate() {
+        csa.push_str(&format!("{}\n", move_to_csa(&boards, turn, m)));
+        if let Some(time) = record.times.get(i).copied().flatten() {
+            csa.push_str(&format!("T{}\n", time));
+        }
+        boards = move_piece(boards, *m);
+        turn = turn.opponent();
+    }
+
+    if let Some(termination) = record.termination {
+        match TERMINATIONS.iter().find(|(_, t)| *t == termination) {
+            Some((word, _)) => csa.push_str(&format!("{}\n", word)),
+            // 反則勝ちは直前に指した側(手番でない側)の反則として書き出す
+            None => csa.push_str(&format!("%{}ILLEGAL_ACTION\n", color_sign(turn.opponent()))),
+        }
+    }
+    csa
+}
+
+// CSA形式の文字列を読み込み、指し手を再生して棋譜に変換する関数
+pub fn parse_csa(csa: &str) -> Result<GameRecord> {
+    let mut headers = vec![];
+    let mut boards = Boards::empty();
+    let mut rest_to_hand = None;
+    let mut record: Option<GameRecord> = None;
+    let mut turn = Color::Black;
+
+    // 1行に','区切りで複数の文を書ける (コメント行を除く)
+    let statements = csa.lines().flat_map(|line| {
+        if line.starts_with('\'') {
+            vec![line]
+        } else {
+            line.split(',').collect()
+        }
+    });
+    for statement in statements {
+        let statement = statement.trim_end();
+        if statement.is_empty() || statement.starts_with('\'') || statement.starts_with('V') {
+            continue;
+        }
+
+        if let Some(record) = &mut record {
+            // 指し手・消費時間・終局
+            if let Some(time) = statement.strip_prefix('T') {
+                let time = time
+                    .parse::<f64>()
+                    .map_err(|_| anyhow!("invalid csa time: {}", statement))?;
+                if let Some(last) = record.times.last_mut() {
+                    *last = Some(time as u32);
+                }
+            } else if statement.starts_with('%') {
+                if let Some(termination) = parse_termination(statement, turn) {
+                    record.termination = Some(termination);
+                    break;
+                }
+            } else if statement.starts_with(['+', '-']) {
+                let m = parse_move(&boards, turn, statement)?;
+                record.moves.push(m);
+                record.times.push(None);
+                boards = move_piece(boards, m);
+                turn = turn.opponent();
+            }
+            continue;
+        }
+
+        if let Some((csa_key, key)) = HEADER_KEYS.iter().find(|(k, _)| statement.starts_with(k)) {
+            headers.push((key.to_string(), statement[csa_key.len()..].to_string()));
+        } else if let Some(info) = statement.strip_prefix('$') {
+            if let Some((key, value)) = info.split_once(':') {
+                headers.push((format!("${}", key), value.to_string()));
+            }
+        } else if let Some(removed) = statement.strip_prefix("PI") {
+            boards = create_initial_board();
+            // 駒落ち: PI82HI22KA のように取り除く駒を指定する
+            for chunk in removed.as_bytes().chunks(4) {
+                let (square, _) = parse_square_piece(chunk)?;
+                let square = square.ok_or(anyhow!("invalid csa PI: {}", statement))?;
+                boards.board[square.y as usize][square.x as usize] = None;
+            }
+        } else if let Some(pieces) = statement
+            .strip_prefix("P+")
+            .map(|p| (Color::Black, p))
+            .or_else(|| statement.strip_prefix("P-").map(|p| (Color::White, p)))
+        {
+            let (color, pieces) = pieces;
+            for chunk in pieces.as_bytes().chunks(4) {
+                if chunk == b"00AL" {
+                    rest_to_hand = Some(color);
+                    continue;
+                }
+                let (square, piece_type) = parse_square_piece(chunk)?;
+                let piece = Piece::new(piece_type, color);
+                match square {
+                    Some(square) => {
+                        boards.board[square.y as usize][square.x as usize] = Some(piece)
+                    }
+                    None => {
+                        if !boards.hand.add(piece) {
+                            bail!("invalid csa hand: {}", statement);
+                        }
+                    }
+                }
+            }
+        } else if let Some(row) = statement.strip_prefix('P') {
+            parse_row(&mut boards, row)?;
+        } else if statement == "+" || statement == "-" {
+            // 手番の行で初期局面が確定する
+            turn = if statement == "+" {
+                Color::Black
+            } else {
+                Color::White
+            };
+            if let Some(color) = rest_to_hand {
+                put_rest_to_hand(&mut boards, color);
+            }
+            let mut r = GameRecord::new(boards, turn);
+            r.headers = headers.clone();
+            record = Some(r);
+        }
+    }
+
+    record.ok_or(anyhow!("csa has no turn line"))
+}
+
+// P1〜P9の1段分を読み込む
+fn parse_row(boards: &mut Boards, row: &str) -> Result<()> {
+    let rank = row
+        .get(0..1)
+        .and_then(|r| r.parse::<usize>().ok())
+        .filter(|r| (1..=BOARD_SIZE).contains(r))
+        .ok_or(anyhow!("invalid csa row: P{}", row))?;
+    let y = BOARD_SIZE - rank;
+    let cells = &row.as_bytes()[1..];
+    for (file, cell) in cells.chunks(3).enumerate().take(BOARD_SIZE) {
+        let x = BOARD_SIZE - 1 - file;
+        boards.board[y][x] = match cell {
+            [b'+' | b'-', code @ ..] => {
+                let color = if cell[0] == b'+' {
+                    Color::Black
+                } else {
+                    Color::White
+                };
+                let code = std::str::from_utf8(code)?;
+                let piece_type =
+                    piece_type_from_code(code).ok_or(anyhow!("invalid csa piece: P{}", row))?;
+                Some(Piece::new(piece_type, color))
+            }
+            _ => None,
+        };
+    }
+    Ok(())
+}
+
+// 盤上と駒台にない駒を全て持ち駒にする (00AL)
+fn put_rest_to_hand(boards: &mut Boards, color: Color) {
+    let full = create_initial_board();
+    for piece_type in HAND_PIECE_TYPES {
+        let total = count_pieces(&full, piece_type);
+        let used = count_pieces(boards, piece_type);
+        for _ in used..total {
+            boards.hand.add(Piece::new(piece_type, color));
+        }
+    }
+}
+
+// 成り駒も含めて盤上と駒台にある駒の数を数える
+fn count_pieces(boards: &Boards, piece_type: PieceType) -> usize {
+    let on_board = boards
+        .board
+        .iter()
+        .flat_map(|row| row.iter())
+        .filter(|p| matches!(p, Some(p) if p.revolute_back().piece_type == piece_type))
+        .count();
+    let in_hand = [Color::Black, Color::White]
+        .iter()
+        .map(|&color| boards.hand.count(piece_type, color) as usize)
+        .sum::<usize>();
+    on_board + in_hand
+}
+
+// 2桁のマス(00は駒台)と駒の記号を読み込む
+fn parse_square_piece(chunk: &[u8]) -> Result<(Option<Position>, PieceType)> {
+    let text = std::str::from_utf8(chunk)?;
+    if text.len() != 4 {
+        bail!("invalid csa piece: {}", text);
+    }
+    let square = parse_square(&text[0..2]).ok_or(anyhow!("invalid csa square: {}", text))?;
+    let piece_type =
+        piece_type_from_code(&text[2..4]).ok_or(anyhow!("invalid csa piece: {}", text))?;
+    Ok((square, piece_type))
+}
+
+// "77"のようなマスを座標に変換する。"00"は駒台を表しNoneを返す
+fn parse_square(text: &str) -> Option<Option<Position>> {
+    let mut digits = text.chars().map(|c| c.to_digit(10));
+    let (Some(Some(file)), Some(Some(rank))) = (digits.next(), digits.next()) else {
+        return None;
+    };
+    if file == 0 && rank == 0 {
+        return Some(None);
+    }
+    let position = Position::new(file as i32 - 1, BOARD_SIZE as i32 - rank as i32, 0);
+    position.is_valid().then_some(Some(position))
+}
+
+// 終局を表す文を読み込む。知らない%から始まる文はNoneを返す
+fn parse_termination(statement: &str, turn: Color) -> Option<Termination> {
+    if let Some((_, t)) = TERMINATIONS.iter().find(|(w, _)| statement == *w) {
+        return Some(*t);
+    }
+    let illegal = match statement {
+        "%HIKIWAKE" => return Some(Termination::Jishogi),
+        "%+ILLEGAL_ACTION" => Color::Black,
+        "%-ILLEGAL_ACTION" => Color::White,
+        _ => return None,
+    };
+    if illegal == turn {
+        Some(Termination::IllegalLoss)
+    } else {
+        Some(Termination::IllegalWin)
+    }
+}
+
+// +7776FU のような指し手を読み込む
+fn parse_move(boards: &Boards, turn: Color, statement: &str) -> Result<LegalMove> {
+    let color = if statement.starts_with('+') {
+        Color::Black
+    } else {
+        Color::White
+    };
+    if color != turn || statement.len() != 7 || !statement.is_ascii() {
+        bail!("invalid csa move: {}", statement);
+    }
+    let from = parse_square(&statement[1..3]).ok_or(anyhow!("invalid csa move: {}", statement))?;
+    let to = parse_square(&statement[3..5])
+        .flatten()
+        .ok_or(anyhow!("invalid csa move: {}", statement))?;
+    let piece_type =
+        piece_type_from_code(&statement[5..7]).ok_or(anyhow!("invalid csa move: {}", statement))?;
+
+    let Some(from) = from else {
+        return find_drop_move(boards, turn, piece_type, to)
+            .ok_or(anyhow!("illegal csa move: {}", statement));
+    };
+    let piece = boards.board[from.y as usize][from.x as usize]
+        .filter(|p| p.color == turn)
+        .ok_or(anyhow!("no piece to move: {}", statement))?;
+    let revolute = if piece.piece_type == piece_type {
+        false
+    } else if piece.can_revolte() && piece.revolute().piece_type == piece_type {
+        true
+    } else {
+        bail!("csa piece does not match the board: {}", statement);
+    };
+    let legal_move = LegalMove { from, to, revolute };
+    if !create_move_range(boards, turn).contains(&legal_move) {
+        bail!("illegal csa move: {}", statement);
+    }
+    Ok(legal_move)
+}
+
+// 指し手をCSAの表記(例: +7776FU, -0055KA)に変換する
+fn move_to_csa(boards: &Boards, turn: Color, legal_move: &LegalMove) -> String {
+    let from = legal_move.from;
+    let piece = legal_move.piece(boards).unwrap();
+    let from = if from.z == 1 {
+        "00".to_string()
+    } else {
+        square_to_csa(from)
+    };
+    let piece = if legal_move.revolute {
+        piece.revolute()
+    } else {
+        piece
+    };
+    format!(
+        "{}{}{}{}",
+        color_sign(turn),
+        from,
+        square_to_csa(legal_move.to),
+        piece_code(piece.piece_type)
+    )
+}
+
+fn square_to_csa(position: Position) -> String {
+    format!("{}{}", position.x + 1, BOARD_SIZE as i32 - position.y)
+}
+
+fn piece_to_csa(piece: &Piece) -> String {
+    format!(
+        "{}{}",
+        color_sign(piece.color),
+        piece_code(piece.piece_type)
+    )
+}
+
+fn piece_code(piece_type: PieceType) -> &'static str {
+    PIECE_CODES
+        .iter()
+        .find(|(_, t)| *t == piece_type)
+        .map(|(code, _)| *code)
+        .unwrap()
+}
+
+fn piece_type_from_code(code: &str) -> Option<PieceType> {
+    PIECE_CODES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, t)| *t)
+}
+
+fn color_sign(color: Color) -> char {
+    match color {
+        Color::Black => '+',
+        Color::White => '-',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sfen::{parse_sfen, INITIAL_SFEN},
+        usi::move_from_usi,
+    };
+
+    // SFENの局面からUSIの手を順に指した棋譜 (消費時間は1秒, 2秒, ...)
+    fn record(sfen: &str, moves: &[&str]) -> GameRecord {
+        let (mut boards, mut turn, _) = parse_sfen(sfen).unwrap();
+        let mut record = GameRecord::new(boards, turn);
+        for (i, usi) in moves.iter().enumerate() {
+            let m = move_from_usi(&boards, turn, usi).unwrap();
+            record.moves.push(m);
+            record.times.push(Some(i as u32 + 1));
+            boards = move_piece(boards, m);
+            turn = turn.opponent();
+        }
+        record
+    }
+
+    #[test]
+    fn parse_board_hands_moves_and_times() {
+        let csa = "V2.2\n\
+            N+sente\n\
+            N-gote\n\
+            P1 *  *  *  *  *  *  *  * -OU\n\
+            P2 *  *  *  *  *  *  *  *  * \n\
+            P3 *  *  *  *  *  *  *  *  * \n\
+            P4 *  *  *  * +FU *  *  *  * \n\
+            P5 *  *  *  *  *  *  *  *  * \n\
+            P6 *  *  *  *  *  *  *  *  * \n\
+            P7 *  *  *  *  *  *  *  *  * \n\
+            P8 *  *  *  *  *  *  *  *  * \n\
+            P9+OU *  *  *  *  *  *  *  * \n\
+            P+00KI\n\
+            P-00FU00FU\n\
+            +\n\
+            +5453TO\nT1\n\
+            -1121OU\nT2\n\
+            +0052KI\nT3\n\
+            -0015FU\nT4\n\
+            %TORYO\n";
+        let mut expected = record(
+            "8k/9/9/4P4/9/9/9/9/K8 b G2p 1",
+            &["5d5c+", "1a2a", "G*5b", "P*1e"],
+        );
+        expected.headers = vec![
+            ("先手".to_string(), "sente".to_string()),
+            ("後手".to_string(), "gote".to_string()),
+        ];
+        expected.termination = Some(Termination::Resign);
+        assert_eq!(parse_csa(csa).unwrap(), expected);
+    }
+
+    #[test]
+    fn export_board_hands_moves_and_times() {
+        let record = record(
+            "8k/9/9/4P4/9/9/9/9/K8 b G2p 1",
+            &["5d5c+", "1a2a", "G*5b", "P*1e"],
+        );
+        let csa = to_csa(&record);
+        let lines = csa.lines().collect::<Vec<_>>();
+        assert_eq!(lines[1], "P1 *  *  *  *  *  *  *  * -OU");
+        assert_eq!(lines[4], "P4 *  *  *  * +FU *  *  *  * ");
+        assert_eq!(lines[9], "P9+OU *  *  *  *  *  *  *  * ");
+        assert_eq!(
+            &lines[10..],
+            [
+                "P+00KI",
+                "P-00FU00FU",
+                "+",
+                "+5453TO",
+                "T1",
+                "-1121OU",
+                "T2",
+                "+0052KI",
+                "T3",
+                "-0015FU",
+                "T4",
+            ]
+        );
+        assert_eq!(parse_csa(&csa).unwrap(), record);
+    }
+
+    #[test]
+    fn round_trip_every_termination() {
+        let terminations = TERMINATIONS
+            .iter()
+            .map(|(_, t)| *t)
+            .chain([Termination::IllegalWin]);
+        for termination in terminations {
+            let mut record = record(INITIAL_SFEN, &["7g7f", "3c3d", "8h2b+"]);
+            record.termination = Some(termination);
+            let csa = to_csa(&record);
+            assert!(csa.starts_with("V2.2\nPI\n+\n"), "{}", csa);
+            assert_eq!(parse_csa(&csa).unwrap(), record, "{}", csa);
+        }
+    }
+
+    #[test]
+    fn illegal_results() {
+        // 後手の手番で終わった棋譜
+        let record = record(INITIAL_SFEN, &["7g7f"]);
+        let parse = |word: &str| {
+            let csa = format!("{}{}\n", to_csa(&record), word);
+            parse_csa(&csa).unwrap().winner()
+        };
+        // %ILLEGAL_MOVEは手番側の反則負け
+        assert_eq!(parse("%ILLEGAL_MOVE"), Some(Color::Black));
+        assert_eq!(parse("%-ILLEGAL_ACTION"), Some(Color::Black));
+        assert_eq!(parse("%+ILLEGAL_ACTION"), Some(Color::White));
+
+        let mut win = record.clone();
+        win.termination = Some(Termination::IllegalWin);
+        assert!(to_csa(&win).ends_with("%+ILLEGAL_ACTION\n"));
+        let mut loss = record;
+        loss.termination = Some(Termination::IllegalLoss);
+        assert!(to_csa(&loss).ends_with("%ILLEGAL_MOVE\n"));
+    }
+
+    #[test]
+    fn handicap_is_written_as_pi() {
+        let boards = create_handicap_board(Handicap::Bishop);
+        let record = GameRecord::new(boards, Color::White);
+        let csa = to_csa(&record);
+        assert!(csa.starts_with("V2.2\nPI22KA\n-\n"), "{}", csa);
+        assert_eq!(parse_csa(&csa).unwrap(), record);
+    }
+}
@@ -1,3 +1,8 @@
+use crate::{
+    board::{get_num_array, Boards},
+    legacy::convert_legacy_records,
+    piece::Color,
+};
 use anyhow::Result;
 use sqlx::{migrate::MigrateDatabase, Sqlite};
 use std::path::Path;
@@ -16,3 +21,58 @@ pub async fn get_connection() -> Result<sqlx::sqlite::SqlitePool> {
     let pool = sqlx::sqlite::SqlitePoolOptions::new().connect_lazy(db_url)?;
     Ok(pool)
 }
+
+// 引き分けの対局のWINNERの値
+pub const DRAW: i8 = 2;
+
+// RECORDSの形式のバージョン
+// 1: 持ち駒を盤面の2ページ目の升に置いた形式, 2: 持ち駒を枚数で表す形式
+pub const RECORDS_VERSION: i32 = 2;
+
+// 対局の盤面の記録と勝者をKIFUテーブルに保存する関数
+// 引き分けの場合はwinnerをNoneにする (WINNERにはDRAWが入る)
+pub async fn insert_kifu(
+    pool: &sqlx::SqlitePool,
+    winner: Option<Color>,
+    boards_record: &[Boards],
+) -> Result<()> {
+    let records = boards_record.iter().map(get_num_array).collect::<Vec<_>>();
+    let record = records.as_slice().concat().concat().concat();
+    let query = sqlx::query("INSERT INTO KIFU (WINNER, RECORDS, VERSION) VALUES (?, ?, ?)")
+        .bind(winner.map_or(DRAW, |w| w as i8))
+        .bind(&record)
+        .bind(RECORDS_VERSION);
+    query.execute(pool).await?;
+    Ok(())
+}
+
+// 古い形式のRECORDSを今の形式に変換する関数
+// 変換できた件数と、変換できなかった対局のIDとエラーを返す
+// 変換できなかった対局は途中の局面だけを書き換えたりせず、古い形式のまま残す
+pub async fn convert_legacy_kifu(
+    pool: &sqlx::SqlitePool,
+) -> Result<(usize, Vec<(i64, anyhow::Error)>)> {
+    let rows: Vec<(i64, Vec<u8>)> =
+        sqlx::query_as("SELECT ID, RECORDS FROM KIFU WHERE VERSION = 1")
+            .fetch_all(pool)
+            .await?;
+    let mut converted = 0;
+    let mut failed = vec![];
+    for (id, records) in rows {
+        let records = match convert_legacy_records(&records) {
+            Ok(records) => records,
+            Err(e) => {
+                failed.push((id, e));
+                continue;
+            }
+        };
+        sqlx::query("UPDATE KIFU SET RECORDS = ?, VERSION = ? WHERE ID = ?")
+            .bind(&records)
+            .bind(RECORDS_VERSION)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        converted += 1;
+    }
+    Ok((converted, failed))
+}
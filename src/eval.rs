@@ -0,0 +1,247 @@
+use crate::{
+    bitboard::{square_xy, Position, PIECE_TYPES},
+    board::{BOARD_SIZE, HAND_PIECE_TYPES},
+    piece::{Color, Piece, PieceType},
+};
+
+// モデルがないときに使う手作りの評価関数
+// 駒割り・駒の位置・玉の守り・大駒の利きの数を手番の側から見た値で足し合わせる
+
+// 盤上の駒の価値 (玉は駒割りに含めない。指し手の並べ替え用に大きな値にしている)
+pub const fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::King => 15000,
+        PieceType::Rook => 990,
+        PieceType::Bishop => 855,
+        PieceType::Gold => 540,
+        PieceType::Silver => 495,
+        PieceType::Knight => 405,
+        PieceType::Lance => 315,
+        PieceType::Pawn => 90,
+        PieceType::Dragon => 1395,
+        PieceType::Horse => 945,
+        PieceType::PromotedSilver => 540,
+        PieceType::PromotedKnight => 540,
+        PieceType::PromotedLance => 540,
+        PieceType::PromotedPawn => 540,
+    }
+}
+
+// 持ち駒の価値 (どこにでも打てるので盤上より少し高くする)
+pub const fn hand_value(piece_type: PieceType) -> i32 {
+    piece_value(piece_type) * 11 / 10
+}
+
+// 駒の位置の評価 [駒の種類][自陣の一番奥からの段]
+// 筋による違いは玉以外は小さいので段だけで決める
+const PIECE_SQUARE: [[i32; BOARD_SIZE]; PieceType::get_max() as usize] = [
+    // 玉: 自陣の奥にいるほど安全
+    [30, 10, -10, -30, -50, -60, -60, -60, -60],
+    // 飛
+    [0, 0, 0, 0, 0, 0, 20, 20, 20],
+    // 角
+    [0, 0, 0, 0, 0, 0, 10, 10, 10],
+    // 金: 玉の近くで守りに使う
+    [5, 10, 10, 5, 0, -5, -5, -5, -5],
+    // 銀
+    [0, 5, 15, 20, 20, 15, 10, 5, 0],
+    // 桂: 跳ねすぎると動けなくなる
+    [0, 0, 10, 20, 30, 30, 20, 0, 0],
+    // 香
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    // 歩
+    [0, 0, 0, 5, 10, 20, 30, 40, 0],
+    // 龍
+    [0, 0, 0, 0, 0, 0, 10, 10, 10],
+    // 馬: 自陣に引くと守りに利く
+    [10, 10, 5, 0, 0, 0, 0, 0, 0],
+    // 成銀・成桂・成香・と: 敵陣で攻めに使う
+    [0, 0, 0, 5, 10, 20, 30, 30, 30],
+    [0, 0, 0, 5, 10, 20, 30, 30, 30],
+    [0, 0, 0, 5, 10, 20, 30, 30, 30],
+    [0, 0, 0, 5, 10, 20, 30, 30, 30],
+];
+
+// 玉の周りの升に相手の駒が1つ利いているごとの減点
+const KING_ATTACK_PENALTY: i32 = 25;
+// 玉の周りにいる金・銀1枚ごとの加点
+const KING_DEFENDER_BONUS: i32 = 15;
+// 大駒・香の利いている升1つごとの加点
+const MOBILITY_WEIGHT: i32 = 3;
+
+// 手番の側から見た評価値を返す関数
+pub fn evaluate(position: &Position) -> i32 {
+    let turn = position.turn();
+    let score = |color: Color| {
+        material(position, color)
+            + piece_square(position, color)
+            + king_safety(position, color)
+            + mobility(position, color)
+    };
+    score(turn) - score(turn.opponent())
+}
+
+fn material(position: &Position, color: Color) -> i32 {
+    let on_board = PIECE_TYPES
+        .iter()
+        .filter(|&&t| t != PieceType::King)
+        .map(|&t| position.pieces(t, color).count() as i32 * piece_value(t))
+        .sum::<i32>();
+    let in_hand = HAND_PIECE_TYPES
+        .iter()
+        .map(|&t| position.hand().count(t, color) as i32 * hand_value(t))
+        .sum::<i32>();
+    on_board + in_hand
+}
+
+// 先手は下(y = 0)から、後手は上から数えた段
+fn relative_rank(color: Color, sq: usize) -> usize {
+    let (_, y) = square_xy(sq);
+    match color {
+        Color::Black => y,
+        Color::White => BOARD_SIZE - 1 - y,
+    }
+}
+
+fn piece_square(position: &Position, color: Color) -> i32 {
+    PIECE_TYPES
+        .iter()
+        .flat_map(|&t| {
+            position
+                .pieces(t, color)
+                .squares()
+                .map(move |sq| PIECE_SQUARE[t as usize - 1][relative_rank(color, sq)])
+        })
+        .sum()
+}
+
+// 玉の周りの升への相手の利きと、玉の周りの金銀の数
+// 玉がない局面(詰将棋など)では0
+fn king_safety(position: &Position, color: Color) -> i32 {
+    let Some(king) = position.king_square(color) else {
+        return 0;
+    };
+    let around = position.attacks_from(Piece::new(PieceType::King, color), king);
+    let attacks = around
+        .squares()
+        .map(|sq| position.attackers_to(sq, color.opponent()).count() as i32)
+        .sum::<i32>();
+    let defenders = (position.pieces(PieceType::Gold, color)
+        | position.pieces(PieceType::Silver, color))
+        & around;
+    defenders.count() as i32 * KING_DEFENDER_BONUS - attacks * KING_ATTACK_PENALTY
+}
+
+fn mobility(position: &Position, color: Color) -> i32 {
+    let own = position.color_pieces(color);
+    [
+        PieceType::Rook,
+        PieceType::Bishop,
+        PieceType::Dragon,
+        PieceType::Horse,
+        PieceType::Lance,
+    ]
+    .iter()
+    .flat_map(|&t| {
+        position
+            .pieces(t, color)
+            .squares()
+            .map(move |sq| (position.attacks_from(Piece::new(t, color), sq) & !own).count() as i32)
+    })
+    .sum::<i32>()
+        * MOBILITY_WEIGHT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        board::{create_legal_moves, move_piece, Boards},
+        sfen::{parse_sfen, INITIAL_SFEN},
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn evaluate_sfen(sfen: &str) -> i32 {
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        evaluate(&Position::from_boards(&boards, turn))
+    }
+
+    // 盤を180度回して先手と後手の駒を入れ替えた局面
+    fn flip(boards: &Boards) -> Boards {
+        let mut flipped = Boards::empty();
+        for y in 0..BOARD_SIZE {
+            for x in 0..BOARD_SIZE {
+                flipped.board[BOARD_SIZE - 1 - y][BOARD_SIZE - 1 - x] =
+                    boards.board[y][x].map(|p| Piece::new(p.piece_type, p.color.opponent()));
+            }
+        }
+        for &piece_type in HAND_PIECE_TYPES.iter() {
+            for color in [Color::Black, Color::White] {
+                for _ in 0..boards.hand.count(piece_type, color) {
+                    flipped.hand.add(Piece::new(piece_type, color.opponent()));
+                }
+            }
+        }
+        flipped
+    }
+
+    #[test]
+    fn evaluation_is_symmetric() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (mut boards, mut turn, _) = parse_sfen(INITIAL_SFEN).unwrap();
+        assert_eq!(evaluate(&Position::from_boards(&boards, turn)), 0);
+        for _ in 0..120 {
+            let moves = create_legal_moves(&boards, turn);
+            if moves.is_empty() {
+                break;
+            }
+            boards = move_piece(boards, moves[rng.gen_range(0..moves.len())]);
+            turn = turn.opponent();
+
+            let score = evaluate(&Position::from_boards(&boards, turn));
+            assert_eq!(
+                score,
+                -evaluate(&Position::from_boards(&flip(&boards), turn))
+            );
+            assert_eq!(
+                score,
+                -evaluate(&Position::from_boards(&boards, turn.opponent()))
+            );
+        }
+    }
+
+    #[test]
+    fn hand_piece_values() {
+        let kings = evaluate_sfen("4k4/9/9/9/9/9/9/9/4K4 b - 1");
+        for &piece_type in HAND_PIECE_TYPES.iter() {
+            assert!(hand_value(piece_type) > piece_value(piece_type));
+            let c = crate::sfen::piece_type_to_char(piece_type);
+            let sfen = format!("4k4/9/9/9/9/9/9/9/4K4 b {} 1", c);
+            assert_eq!(evaluate_sfen(&sfen) - kings, hand_value(piece_type));
+            let sfen = format!("4k4/9/9/9/9/9/9/9/4K4 w {} 1", c);
+            assert_eq!(evaluate_sfen(&sfen) + kings, -hand_value(piece_type));
+        }
+    }
+
+    #[test]
+    fn promoted_piece_values() {
+        for piece_type in [
+            PieceType::Rook,
+            PieceType::Bishop,
+            PieceType::Silver,
+            PieceType::Knight,
+            PieceType::Lance,
+            PieceType::Pawn,
+        ] {
+            let promoted = Piece::new(piece_type, Color::Black).revolute().piece_type;
+            assert!(piece_value(promoted) > piece_value(piece_type));
+        }
+        // 玉から離れた9五の歩とと金 (段の評価は同じ)
+        let pawn = evaluate_sfen("4k4/9/9/9/P8/9/9/9/4K4 b - 1");
+        let tokin = evaluate_sfen("4k4/9/9/9/+P8/9/9/9/4K4 b - 1");
+        assert_eq!(
+            tokin - pawn,
+            piece_value(PieceType::PromotedPawn) - piece_value(PieceType::Pawn)
+        );
+    }
+}
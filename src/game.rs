@@ -1,19 +1,43 @@
 use std::{sync::Arc, vec};
 
 use crate::{
+    bitboard,
     board::{
-        create_initial_board, create_move_range, get_num_array, is_checked, is_checkmate,
-        move_piece, print_boards, Boards, LegalMove, BOARD_SIZE,
+        create_handicap_board, create_legal_moves, declare_entering_king, do_move, is_checked,
+        is_checkmate, move_piece, print_boards, undo_move, Boards, Declaration, Handicap,
+        ImpasseRule, LegalMove, UndoMove,
     },
+    db::insert_kifu,
     inference::Inference,
+    mcts::{self, MctsConfig},
     piece::{Color, Piece},
+    record::{GameRecord, Termination},
+    search::{search, SearchLimits, SearchResult},
+    sfen::{parse_sfen, to_sfen},
+    tsume::{self, TsumeResult},
+    tt::TranspositionTable,
+    validate::validate,
+    zobrist::hash,
 };
-use anyhow::Result;
+use anyhow::{bail, Result};
 use rayon::prelude::*;
 
+// 1手詰めがなく王手がある局面で、詰みを探すノード数の上限
+const MATE_SEARCH_NODES: u64 = 2_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GameState {
     Playing,
+    // 勝った側
     Checkmate(Color),
+    // 千日手による引き分け
+    Draw,
+    // 連続王手の千日手 (王手をかけ続けた側の負け、勝った側を持つ)
+    PerpetualCheck(Color),
+    // 入玉宣言勝ち (宣言した側を持つ)
+    EnteringKing(Color),
+    // 入玉宣言による持将棋 (24点法で点数が足りない場合の引き分け)
+    Jishogi,
 }
 
 pub struct Game {
@@ -21,21 +45,84 @@ pub struct Game {
     turn: Color,
     inference: Arc<Inference>,
     boards_record: Vec<Boards>,
+    // 指した手を戻すための記録
+    undo_record: Vec<UndoMove>,
+    // 初期局面からの各局面のZobristハッシュ
+    keys: Vec<u64>,
+    game_record: GameRecord,
+    state: GameState,
+    impasse_rule: ImpasseRule,
+    start_move_number: u32,
+    started_at: chrono::DateTime<chrono::Local>,
     pool: sqlx::SqlitePool,
 }
 
 impl Game {
     // mode true: train, false: play
     pub fn new(pool: sqlx::SqlitePool, inference: Arc<Inference>) -> Self {
-        let boards = create_initial_board();
+        Self::with_handicap(pool, inference, Handicap::Even)
+    }
+
+    // 手合割の初期局面から対局を開始する (駒落ちは後手から指す)
+    pub fn with_handicap(
+        pool: sqlx::SqlitePool,
+        inference: Arc<Inference>,
+        handicap: Handicap,
+    ) -> Self {
+        let boards = create_handicap_board(handicap);
+        let turn = handicap.first_turn();
         Game {
             boards,
-            turn: Color::Black,
+            turn,
             inference,
             boards_record: vec![],
+            undo_record: vec![],
+            keys: vec![hash(&boards, turn)],
+            game_record: GameRecord::new(boards, turn),
+            state: GameState::Playing,
+            impasse_rule: ImpasseRule::Point24,
+            start_move_number: 1,
+            started_at: chrono::Local::now(),
             pool,
         }
     }
+
+    // SFENで指定された局面から対局を開始する
+    pub fn from_sfen(
+        pool: sqlx::SqlitePool,
+        inference: Arc<Inference>,
+        sfen: &str,
+    ) -> Result<Self> {
+        let (boards, turn, move_number) = parse_sfen(sfen)?;
+        let errors = validate(&boards, turn);
+        if !errors.is_empty() {
+            let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+            bail!("invalid position: {}", errors.join(", "));
+        }
+        Ok(Game {
+            boards,
+            turn,
+            inference,
+            boards_record: vec![],
+            undo_record: vec![],
+            keys: vec![hash(&boards, turn)],
+            game_record: GameRecord::new(boards, turn),
+            state: GameState::Playing,
+            impasse_rule: ImpasseRule::Point24,
+            start_move_number: move_number,
+            started_at: chrono::Local::now(),
+            pool,
+        })
+    }
+
+    // 現在の局面をSFENで返す
+    pub fn sfen(&self) -> String {
+        to_sfen(
+            &self.boards,
+            self.turn,
+            self.start_move_number + self.boards_record.len() as u32,
+        )
+    }
     #[allow(unused)]
     pub fn print(&self) {
         print_boards(&self.boards)
@@ -45,122 +132,411 @@ impl Game {
         self.turn
     }
 
+    pub const fn boards(&self) -> &Boards {
+        &self.boards
+    }
+
+    pub const fn state(&self) -> GameState {
+        self.state
+    }
+
+    // 入玉宣言の点数のルールを設定する (デフォルトは24点法)
+    pub fn set_impasse_rule(&mut self, rule: ImpasseRule) {
+        self.impasse_rule = rule;
+    }
+
+    // 現在の手番の側が入玉宣言した場合の結果 (宣言できない場合はNone)
+    pub fn declaration(&self) -> Option<Declaration> {
+        if self.state != GameState::Playing {
+            return None;
+        }
+        declare_entering_king(&self.boards, self.turn, self.impasse_rule)
+    }
+
+    // 現在の手番の側が入玉宣言する
+    // 宣言できた場合は終局し、そのときの状態を返す
+    pub fn declare(&mut self) -> Option<GameState> {
+        self.state = match self.declaration()? {
+            Declaration::Win => GameState::EnteringKing(self.turn),
+            Declaration::Draw => GameState::Jishogi,
+        };
+        Some(self.state)
+    }
+
+    // 入玉宣言で勝てる場合だけ宣言する (持将棋になる宣言は自動ではしない)
+    fn declare_win(&mut self) -> Option<GameState> {
+        if self.declaration()? != Declaration::Win {
+            return None;
+        }
+        self.declare()
+    }
+
     pub async fn save(&self) -> Result<()> {
-        let records = self
-            .boards_record
-            .iter()
-            .map(|boards| get_num_array(boards))
-            .collect::<Vec<_>>();
-        let record = records.as_slice().concat().concat().concat();
-        let query = sqlx::query("INSERT INTO KIFU (WINNER, RECORDS) VALUES (?, ?)")
-            .bind(self.turn as i8)
-            .bind(&record);
-        query.execute(&self.pool).await?;
+        // 終局前に保存する場合は現在の手番を勝者として扱う
+        let winner = match self.state {
+            GameState::Playing => Some(self.turn),
+            GameState::Checkmate(color)
+            | GameState::PerpetualCheck(color)
+            | GameState::EnteringKing(color) => Some(color),
+            GameState::Draw | GameState::Jishogi => None,
+        };
+        insert_kifu(&self.pool, winner, &self.boards_record).await?;
         //self.inference.train(&self.boards_record, self.turn)?;
         Ok(())
     }
 
     pub fn next(&mut self) -> Result<GameState> {
-        let move_range = create_move_range(&self.boards, self.turn);
-        let legal_boards = move_range
-            .par_iter()
-            .filter_map(|range| {
-                let boards = move_piece(self.boards, *range);
-                // 打ち歩詰めは除外
-                if range.from.z == 1
-                    && (range.from.y < 2 || range.from.y > BOARD_SIZE as i32 - 2)
-                    && is_checkmate(&boards, self.turn.opponent())
-                {
-                    return None;
-                }
-                Some(boards)
-            })
-            .collect::<Vec<_>>();
-        let checkmate_boards = legal_boards
-            .par_iter()
-            .filter_map(|&boards| {
-                if is_checkmate(&boards, self.turn.opponent()) {
-                    Some(boards)
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
-
-        // 詰められるときはそれを使う
-        if let Some(checkmate_board) = checkmate_boards.first() {
-            self.boards = *checkmate_board;
-            self.boards_record.push(*checkmate_board);
-            return Ok(GameState::Checkmate(self.turn));
+        // 入玉宣言で勝てる場合は宣言して終局
+        if let Some(state) = self.declare_win() {
+            return Ok(state);
         }
+        // 打てる手がない場合は詰み
+        let Some((best_move, best_boards, checkmate)) = self.search_next()? else {
+            self.turn = self.turn.opponent();
+            self.state = GameState::Checkmate(self.turn);
+            return Ok(self.state);
+        };
 
-        // 王手が解除できない or 自殺手は除外 or 千日手
-        let next_boards = legal_boards
-            .par_iter()
-            .filter(|&boards| {
-                if is_checked(&boards[0], self.turn) {
-                    return false;
-                }
-                if self
-                    .boards_record
-                    .par_iter()
-                    .find_first(|&r| r == boards)
-                    .is_some()
-                {
-                    return false;
-                }
-                true
-            })
-            .cloned()
-            .collect::<Vec<_>>();
+        // 盤面の更新
+        let (_, key) = self.after_move(best_move);
+        let repetition = self.repetition(key, &best_boards);
+        self.push_move(best_move);
+        if checkmate {
+            self.state = GameState::Checkmate(self.turn);
+            return Ok(self.state);
+        }
+        self.turn = self.turn.opponent();
+        self.state = repetition;
+        Ok(self.state)
+    }
 
+    // モンテカルロ木探索で次の一手を選んで指す (自己対局用)
+    // 序盤は訪問回数に応じた確率で選ぶので、同じ対局ばかりにならない
+    pub fn next_with_mcts(&mut self, config: &MctsConfig) -> Result<GameState> {
+        // 入玉宣言で勝てる場合は宣言して終局
+        if let Some(state) = self.declare_win() {
+            return Ok(state);
+        }
+        // 詰みがあれば探索せずに詰ませに行く
+        let mate = self
+            .mate_in_1()
+            .or_else(|| self.find_mate(config.mate_search_nodes));
+        if let Some(m) = mate {
+            return Ok(self.play_next(&m));
+        }
+        let rng = &mut rand::thread_rng();
+        let visits = mcts::search(
+            &self.boards,
+            self.turn,
+            self.inference.as_ref(),
+            config,
+            rng,
+        )?;
+        let temperature = if self.move_count() < config.temperature_moves {
+            config.temperature
+        } else {
+            0.0
+        };
         // 打てる手がない場合は詰み
-        if next_boards.len() == 0 {
+        let Some(best_move) = mcts::select_move(&visits, temperature, rng) else {
             self.turn = self.turn.opponent();
-            return Ok(GameState::Checkmate(self.turn));
+            self.state = GameState::Checkmate(self.turn);
+            return Ok(self.state);
+        };
+        Ok(self.play_next(&best_move))
+    }
+
+    // 相手の合法手がなくなる手(1手詰め)を返す
+    fn mate_in_1(&self) -> Option<LegalMove> {
+        let position = bitboard::Position::from_boards(&self.boards, self.turn);
+        position
+            .legal_moves()
+            .par_iter()
+            .find_first(|m| position.move_piece(m).legal_moves().is_empty())
+            .map(|m| m.to_legal_move(self.turn))
+    }
+
+    // 手番の側から王手の連続で詰む手順があれば最初の手を返す
+    // 王手をかける手がない局面では探さない
+    fn find_mate(&self, max_nodes: u64) -> Option<LegalMove> {
+        let position = bitboard::Position::from_boards(&self.boards, self.turn);
+        if max_nodes == 0 || position.check_moves().is_empty() {
+            return None;
         }
-        // 打てる手の中から最善を選択
-        let best_boards = self.inference.select_best_board(&next_boards, self.turn)?;
+        match tsume::solve(&self.boards, self.turn, max_nodes) {
+            TsumeResult::Mate(moves) => moves.first().copied(),
+            _ => None,
+        }
+    }
 
-        // 盤面の更新
-        self.boards = best_boards;
-        self.boards_record.push(best_boards);
-        self.turn = self.turn.opponent();
-        Ok(GameState::Playing)
+    // 次の一手を選択する (盤面は更新しない)
+    // 打てる手がない場合はNoneを返す
+    pub fn best_move(&self) -> Result<Option<LegalMove>> {
+        Ok(self.search_next()?.map(|(m, _, _)| m))
+    }
+
+    // αβ探索で次の一手を読む (盤面は更新しない)
+    // 深さごとの結果はon_iterationで受け取れる
+    pub fn search(
+        &self,
+        tt: &TranspositionTable,
+        limits: SearchLimits,
+        on_iteration: impl FnMut(&SearchResult),
+    ) -> Result<SearchResult> {
+        search(
+            &self.boards,
+            self.turn,
+            self.inference.as_ref(),
+            tt,
+            limits,
+            on_iteration,
+        )
+    }
+
+    // 次の一手とその結果の盤面、相手を詰ませたかどうかを返す
+    fn search_next(&self) -> Result<Option<(LegalMove, Boards, bool)>> {
+        // 相手の合法手がなくなる手があれば詰ませる
+        if let Some(m) = self.mate_in_1() {
+            return Ok(Some((m, move_piece(self.boards, m), true)));
+        }
+
+        // 自殺手・打ち歩詰めを除いた合法手のうち、連続王手の千日手で負けになる手は除外
+        let (next_moves, next_boards): (Vec<_>, Vec<_>) =
+            create_legal_moves(&self.boards, self.turn)
+                .into_iter()
+                .filter_map(|m| {
+                    let (boards, key) = self.after_move(m);
+                    let lose = matches!(
+                        self.repetition(key, &boards),
+                        GameState::PerpetualCheck(winner) if winner != self.turn
+                    );
+                    (!lose).then_some((m, boards))
+                })
+                .unzip();
+
+        if next_boards.is_empty() {
+            return Ok(None);
+        }
+        // 1手詰めがなくても王手の連続で詰む場合はその手を選ぶ (連続王手の千日手になる手は除く)
+        let mate = self.find_mate(MATE_SEARCH_NODES);
+        if let Some(m) = mate.filter(|m| next_moves.contains(m)) {
+            let boards = move_piece(self.boards, m);
+            return Ok(Some((m, boards, false)));
+        }
+        // 打てる手の中から最善を選択
+        let index = self.inference.select_best_index(&next_boards, self.turn)?;
+        Ok(Some((next_moves[index], next_boards[index], false)))
     }
 
     pub fn get_legal_moves(&self) -> Result<Vec<(Piece, LegalMove)>, GameState> {
-        let move_range = create_move_range(&self.boards, self.turn);
-        let moves = move_range
-            .iter()
-            .filter(|&range| {
-                let boards = move_piece(self.boards, *range);
-                // 自殺手・打ち歩詰めは除外
-                !(range.from.z == 1
-                    && (range.from.y < 2 || range.from.y > BOARD_SIZE as i32 - 2)
-                    && is_checkmate(&boards, self.turn.opponent()))
-                    && !is_checked(&boards[0], self.turn)
-            })
-            .cloned()
-            .collect::<Vec<_>>();
-        if moves.len() == 0 {
+        let moves = create_legal_moves(&self.boards, self.turn);
+        if moves.is_empty() {
             return Err(GameState::Checkmate(self.turn.opponent()));
         }
         let moves = moves
-            .iter()
-            .map(|m| {
-                let p =
-                    self.boards[m.from.z as usize][m.from.y as usize][m.from.x as usize].unwrap();
-                (p, m.clone())
-            })
+            .into_iter()
+            .map(|m| (m.piece(&self.boards).unwrap(), m))
             .collect();
         Ok(moves)
     }
 
-    pub fn play_next(&mut self, movement: &LegalMove) {
-        let boards = move_piece(self.boards, *movement);
-        self.boards = boards;
-        self.boards_record.push(boards);
+    pub fn play_next(&mut self, movement: &LegalMove) -> GameState {
+        let (boards, key) = self.after_move(*movement);
+        self.state = self.repetition(key, &boards);
+        self.push_move(*movement);
         self.turn = self.turn.opponent();
+        self.state
+    }
+
+    // 指し手を指した後の局面とそのハッシュを返す (盤面は更新しない)
+    fn after_move(&self, movement: LegalMove) -> (Boards, u64) {
+        let mut boards = self.boards;
+        let undo = do_move(&mut boards, movement);
+        (boards, self.key() ^ undo.key)
+    }
+
+    // 盤面をその場で動かし、棋譜と戻すための記録に追加する (手番は変えない)
+    fn push_move(&mut self, movement: LegalMove) {
+        let undo = do_move(&mut self.boards, movement);
+        self.keys.push(self.key() ^ undo.key);
+        self.undo_record.push(undo);
+        self.boards_record.push(self.boards);
+        self.game_record.moves.push(movement);
+    }
+
+    // 最後の一手を戻す (終局していた場合は対局中に戻る)
+    // 戻す手がない場合はNoneを返す
+    pub fn undo(&mut self) -> Option<LegalMove> {
+        let undo = self.undo_record.pop()?;
+        undo_move(&mut self.boards, &undo);
+        self.game_record.moves.pop();
+        self.boards_record.pop();
+        self.keys.pop();
+        self.turn = self.game_record.last_turn();
+        self.state = GameState::Playing;
+        Some(undo.legal_move)
+    }
+
+    // 指した手の数
+    pub fn move_count(&self) -> usize {
+        self.game_record.moves.len()
+    }
+
+    // 現在の局面のZobristハッシュ
+    pub fn key(&self) -> u64 {
+        *self.keys.last().unwrap()
+    }
+
+    // 現在の手番の側が指して次の局面がboards(ハッシュがkey)になったときの千日手の判定
+    // 同一局面(盤面・持ち駒・手番)が4回目ならDraw、
+    // その間どちらかの指し手が全て王手ならその側の負けになる
+    fn repetition(&self, key: u64, boards: &Boards) -> GameState {
+        let n = self.keys.len();
+        // ハッシュには手番も含まれるので、一致するのは2手ずつ遡った局面だけ
+        let same = (0..n)
+            .rev()
+            .filter(|&i| self.keys[i] == key)
+            .collect::<Vec<_>>();
+        if same.len() < 3 {
+            return GameState::Playing;
+        }
+        let mut history = vec![self.game_record.initial_boards];
+        history.extend_from_slice(&self.boards_record);
+        history.push(*boards);
+
+        // lastから2手ずつ遡り、1回目の出現より後の局面で常にcolorが王手されていたか
+        let first = *same.last().unwrap();
+        let all_checked = |last: usize, color: Color| {
+            (first + 1..=last)
+                .rev()
+                .step_by(2)
+                .all(|k| is_checked(&history[k].board, color))
+        };
+        let mover = self.turn;
+        if all_checked(n, mover.opponent()) {
+            GameState::PerpetualCheck(mover.opponent())
+        } else if all_checked(n - 1, mover) {
+            GameState::PerpetualCheck(mover)
+        } else {
+            GameState::Draw
+        }
+    }
+
+    // 対局の記録を返す (KIFなどの棋譜の書き出し用)
+    pub fn record(&self) -> GameRecord {
+        let mut record = self.game_record.clone();
+        record.headers.push((
+            "開始日時".to_string(),
+            self.started_at.format("%Y/%m/%d %H:%M:%S").to_string(),
+        ));
+        record.termination = termination(self.state, &self.boards, record.last_turn());
+        record
+    }
+}
+
+// 対局の状態を棋譜の終局理由にする (last_turnは最終局面で手番の側)
+fn termination(state: GameState, boards: &Boards, last_turn: Color) -> Option<Termination> {
+    match state {
+        GameState::Draw => Some(Termination::Sennichite),
+        // 連続王手の千日手は王手をかけ続けた側の反則負け
+        // 勝った側が手番なら手番側の反則勝ち、そうでなければ手番側の反則負け
+        GameState::PerpetualCheck(winner) if winner == last_turn => Some(Termination::IllegalWin),
+        GameState::PerpetualCheck(_) => Some(Termination::IllegalLoss),
+        // 入玉宣言は手番の側が指さずに行う
+        GameState::EnteringKing(_) => Some(Termination::EnteringKing),
+        GameState::Jishogi => Some(Termination::Jishogi),
+        _ if is_checkmate(boards, last_turn) => Some(Termination::Checkmate),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{board::create_initial_board, usi::move_from_usi};
+
+    #[test]
+    fn perpetual_check_by_last_mover_is_illegal_win() {
+        // 直前に指した先手が王手をかけ続けていた場合、手番の後手の勝ち
+        let boards = create_initial_board();
+        let termination = termination(
+            GameState::PerpetualCheck(Color::White),
+            &boards,
+            Color::White,
+        );
+        assert_eq!(termination, Some(Termination::IllegalWin));
+        assert_eq!(
+            termination.unwrap().winner(Color::White),
+            Some(Color::White)
+        );
+    }
+
+    #[test]
+    fn perpetual_check_by_side_to_move_is_illegal_loss() {
+        // 手番の後手が王手をかけ続けていた場合、直前に指した先手の勝ち
+        let boards = create_initial_board();
+        let termination = termination(
+            GameState::PerpetualCheck(Color::Black),
+            &boards,
+            Color::White,
+        );
+        assert_eq!(termination, Some(Termination::IllegalLoss));
+        assert_eq!(
+            termination.unwrap().winner(Color::White),
+            Some(Color::Black)
+        );
+    }
+
+    #[tokio::test]
+    async fn undo_restores_position() {
+        let pool = sqlx::SqlitePool::connect_lazy("sqlite::memory:").unwrap();
+        let mut game = Game::new(pool, Arc::new(Inference::init().unwrap()));
+        let initial_sfen = game.sfen();
+        let initial_key = game.key();
+        // 角交換して角を打つ (駒を取る手・成る手・打つ手を含む)
+        let mut sfens = vec![];
+        for usi in ["7g7f", "3c3d", "8h2b+", "3a2b", "B*4e"] {
+            sfens.push(game.sfen());
+            let m = move_from_usi(game.boards(), game.current_turn(), usi).unwrap();
+            game.play_next(&m);
+        }
+        while let Some(m) = game.undo() {
+            assert_eq!(game.sfen(), sfens.pop().unwrap(), "{:?}", m);
+        }
+        assert_eq!(game.sfen(), initial_sfen);
+        assert_eq!(game.key(), initial_key);
+        assert_eq!(game.move_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn mate_that_exposes_own_king_is_not_played() {
+        let pool = sqlx::SqlitePool::connect_lazy("sqlite::memory:").unwrap();
+        let inference = Arc::new(Inference::init().unwrap());
+        // 2三の金は1二の角に釘付けにされているので、2二金は詰みの形でも指せない
+        let mut game = Game::from_sfen(pool, inference, "8k/8b/7G1/9/5K1L1/9/9/9/9 b - 1").unwrap();
+        let pinned = move_from_usi(game.boards(), game.current_turn(), "2c2b").unwrap();
+        let m = game.best_move().unwrap().unwrap();
+        assert_ne!(m, pinned);
+        assert!(create_legal_moves(game.boards(), game.current_turn()).contains(&m));
+        assert_eq!(game.next().unwrap(), GameState::Playing);
+    }
+
+    #[tokio::test]
+    async fn mate_search_runs_only_with_checks() {
+        let pool = sqlx::SqlitePool::connect_lazy("sqlite::memory:").unwrap();
+        let inference = Arc::new(Inference::init().unwrap());
+        // 3手詰め
+        let game = Game::from_sfen(
+            pool.clone(),
+            inference.clone(),
+            "7nl/7k1/6ppp/9/9/9/9/9/K8 b RG 1",
+        )
+        .unwrap();
+        assert_eq!(game.mate_in_1(), None);
+        assert!(game.find_mate(MATE_SEARCH_NODES).is_some());
+        assert_eq!(game.find_mate(0), None);
+        // 王手をかける手がない
+        let game = Game::new(pool, inference);
+        assert_eq!(game.find_mate(MATE_SEARCH_NODES), None);
     }
 }
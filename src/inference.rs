@@ -1,6 +1,10 @@
 use crate::{
-    board::{Boards, BOARD_SIZE, PAGE_SIZE},
+    bitboard::Position,
+    board::{get_num_array, Boards, BOARD_SIZE, PAGE_SIZE},
+    eval,
+    mcts::BatchEvaluator,
     piece::{Color, PieceType},
+    search::Evaluator,
 };
 use anyhow::Result;
 use rand::Rng;
@@ -13,18 +17,17 @@ pub struct Inference {
 }
 
 impl Inference {
+    // モデルがなければ手作りの評価関数を使う (読み込めたかはis_use_modelで分かる)
     pub fn init() -> Result<Self> {
         let model_path = "model/model";
         let path = std::path::Path::new(model_path);
         let i = if path.exists() {
-            println!("load model");
             let (graph, bundle) = Self::init_session(model_path)?;
             Self {
                 graph: Some(graph),
                 bundle: Some(bundle),
             }
         } else {
-            println!("load model failed");
             Self {
                 graph: None,
                 bundle: None,
@@ -37,6 +40,15 @@ impl Inference {
         self.graph.is_some() && self.bundle.is_some()
     }
 
+    // モデルを読み込めたかどうかの表示用メッセージ
+    pub fn load_message(&self) -> &'static str {
+        if self.is_use_model() {
+            "load model"
+        } else {
+            "load model failed"
+        }
+    }
+
     pub fn init_session(file_name: &str) -> Result<(Graph, SavedModelBundle)> {
         let mut graph = Graph::new();
         let bundle =
@@ -46,6 +58,12 @@ impl Inference {
     }
 
     pub fn select_best_board(&self, boards: &[Boards], turn: Color) -> Result<Boards> {
+        let index = self.select_best_index(boards, turn)?;
+        Ok(boards[index])
+    }
+
+    // 盤面の中から最善のもののインデックスを返す
+    pub fn select_best_index(&self, boards: &[Boards], turn: Color) -> Result<usize> {
         if let (Some(graph), Some(bundle)) = (&self.graph, &self.bundle) {
             let board_list = Self::inference(boards, graph, bundle)?;
 
@@ -60,11 +78,19 @@ impl Inference {
                 })
                 .cloned()
                 .unwrap();
-            Ok(boards[index].clone())
+            Ok(index)
         } else {
+            // モデルがない場合は手作りの評価関数で選ぶ (同じ評価値の手からはランダムに選ぶ)
+            let scores = boards
+                .iter()
+                .map(|b| -eval::evaluate(&Position::from_boards(b, turn.opponent())))
+                .collect::<Vec<_>>();
+            let best = *scores.iter().max().unwrap();
+            let candidates = (0..boards.len())
+                .filter(|&i| scores[i] == best)
+                .collect::<Vec<_>>();
             let rng = &mut rand::thread_rng();
-            let index = rng.gen_range(0..boards.len());
-            Ok(boards[index])
+            Ok(candidates[rng.gen_range(0..candidates.len())])
         }
     }
 
@@ -81,11 +107,12 @@ impl Inference {
         let input_node = graph.operation_by_name_required(&input_info.name().name)?;
         let output_node = graph.operation_by_name_required(&output_info.name().name)?;
 
-        let boards = boards
+        // 学習時と同じ形式に変換してからf32にする
+        let data = boards
             .iter()
-            .map(|board| get_num_array(board))
+            .flat_map(|board| get_num_array(board).concat().concat())
+            .map(f32::from)
             .collect::<Vec<_>>();
-        let data = boards.concat().concat().concat();
         // 入力Tensorの作成
         let input_tensor: tensorflow::Tensor<f32> = Tensor::new(&[
             boards.len() as u64,
@@ -112,28 +139,77 @@ impl Inference {
     }
 }
 
-type BoardAsNum = [[[f32; PieceType::get_max() as usize * PAGE_SIZE * 2]; BOARD_SIZE]; BOARD_SIZE];
-fn get_num_array(boards: &Boards) -> BoardAsNum {
-    let mut b: BoardAsNum =
-        [[[0.0; PieceType::get_max() as usize * PAGE_SIZE * 2]; BOARD_SIZE]; BOARD_SIZE];
-    boards.iter().enumerate().for_each(|(z, board)| {
-        board.iter().enumerate().for_each(|(y, row)| {
-            row.iter().enumerate().for_each(|(x, p)| {
-                if let Some(piece) = p {
-                    let z_index = (piece.get_u8() as usize
-                        + match piece.color {
-                            Color::Black => 0,
-                            Color::White => PieceType::get_max() as usize,
-                        })
-                        * match z {
-                            0 => 1,
-                            _ => 2,
-                        }
-                        - 1;
-                    b[x][y][z_index] = 1.0;
-                }
-            });
-        });
-    });
-    b
+// 勝率を評価値に変換するときの係数 (勝率75%でおよそ660)
+const WIN_RATE_SCALE: f32 = 600.0;
+
+impl Evaluator for Inference {
+    // 手番の側の勝率をロジットで評価値に変換する
+    // モデルがない場合は手作りの評価関数を使う
+    fn evaluate(&self, position: &Position) -> Result<i32> {
+        let (Some(graph), Some(bundle)) = (&self.graph, &self.bundle) else {
+            return Ok(eval::evaluate(position));
+        };
+        let result = Self::inference(&[position.to_boards()], graph, bundle)?;
+        let (_, rate) = result[0];
+        let p = rate[position.turn() as usize].clamp(0.001, 0.999);
+        Ok((WIN_RATE_SCALE * (p / (1.0 - p)).ln()) as i32)
+    }
+}
+
+impl BatchEvaluator for Inference {
+    // 手番の側の勝率をまとめて推論する
+    // モデルがない場合は手作りの評価関数の値を勝率に変換する
+    fn evaluate_batch(&self, positions: &[Position]) -> Result<Vec<f32>> {
+        let (Some(graph), Some(bundle)) = (&self.graph, &self.bundle) else {
+            return Ok(positions
+                .iter()
+                .map(|p| 1.0 / (1.0 + (-eval::evaluate(p) as f32 / WIN_RATE_SCALE).exp()))
+                .collect());
+        };
+        let boards = positions.iter().map(|p| p.to_boards()).collect::<Vec<_>>();
+        let result = Self::inference(&boards, graph, bundle)?;
+        Ok(result
+            .iter()
+            .zip(positions)
+            .map(|((_, rate), p)| rate[p.turn() as usize])
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        board::{create_legal_moves, move_piece},
+        sfen::parse_sfen,
+    };
+
+    #[test]
+    fn without_model_captures_hanging_rook() {
+        let inference = Inference {
+            graph: None,
+            bundle: None,
+        };
+        assert!(!inference.is_use_model());
+        // どちらの手番でもただの飛車を取る
+        for sfen in [
+            "4k4/9/9/9/8r/9/9/9/4K3R b - 1",
+            "r3k4/9/9/9/R8/9/9/9/4K4 w - 1",
+        ] {
+            let (boards, turn, _) = parse_sfen(sfen).unwrap();
+            let next = create_legal_moves(&boards, turn)
+                .into_iter()
+                .map(|m| move_piece(boards, m))
+                .collect::<Vec<_>>();
+            let index = inference.select_best_index(&next, turn).unwrap();
+            let rooks = next[index]
+                .board
+                .iter()
+                .flatten()
+                .flatten()
+                .filter(|p| p.revolute_back().piece_type == PieceType::Rook)
+                .count();
+            assert_eq!(rooks, 1, "{}", sfen);
+        }
+    }
 }
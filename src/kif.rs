@@ -0,0 +1,652 @@
+use crate::{
+    board::{
+        create_handicap_board, create_move_range, find_drop_move, move_piece, Boards, Handicap,
+        LegalMove, Position, BOARD_SIZE, HAND_PIECE_TYPES,
+    },
+    piece::{Color, Piece, PieceType},
+    record::{GameRecord, Termination},
+};
+use anyhow::{anyhow, bail, Result};
+
+// KIF形式の棋譜の読み書き
+// 読み書きはUTF-8の文字列(.kifu相当)で行い、Shift_JISのファイル(.kif)はdecode_kifで変換する
+
+const FILE_CHARS: [char; BOARD_SIZE] = ['１', '２', '３', '４', '５', '６', '７', '８', '９'];
+const RANK_CHARS: [char; BOARD_SIZE] = ['一', '二', '三', '四', '五', '六', '七', '八', '九'];
+const MOVE_HEADER: &str = "手数----指手---------消費時間--";
+const HANDICAP_KEY: &str = "手合割";
+// 指し手の欄の幅 (半角換算)
+const MOVE_WIDTH: usize = 14;
+
+// 駒の名前 (先に書いてあるものを書き出しに使う)
+const PIECE_NAMES: [(&str, PieceType); 19] = [
+    ("玉", PieceType::King),
+    ("王", PieceType::King),
+    ("飛", PieceType::Rook),
+    ("角", PieceType::Bishop),
+    ("金", PieceType::Gold),
+    ("銀", PieceType::Silver),
+    ("桂", PieceType::Knight),
+    ("香", PieceType::Lance),
+    ("歩", PieceType::Pawn),
+    ("龍", PieceType::Dragon),
+    ("竜", PieceType::Dragon),
+    ("馬", PieceType::Horse),
+    ("成銀", PieceType::PromotedSilver),
+    ("全", PieceType::PromotedSilver),
+    ("成桂", PieceType::PromotedKnight),
+    ("圭", PieceType::PromotedKnight),
+    ("成香", PieceType::PromotedLance),
+    ("杏", PieceType::PromotedLance),
+    ("と", PieceType::PromotedPawn),
+];
+
+const TERMINATIONS: [(&str, Termination); 9] = [
+    ("投了", Termination::Resign),
+    ("詰み", Termination::Checkmate),
+    ("切れ負け", Termination::TimeUp),
+    ("千日手", Termination::Sennichite),
+    ("持将棋", Termination::Jishogi),
+    ("入玉勝ち", Termination::EnteringKing),
+    ("反則勝ち", Termination::IllegalWin),
+    ("反則負け", Termination::IllegalLoss),
+    ("中断", Termination::Interrupt),
+];
+
+const KANJI_NUMBERS: [&str; 10] = ["", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+
+// 棋譜をKIF形式の文字列に変換する関数
+pub fn to_kif(record: &GameRecord) -> String {
+    let mut kif = String::from("# ---- shogi-ml 棋譜ファイル ----\n");
+    for (key, value) in &record.headers {
+        kif.push_str(&format!("{}：{}\n", key, value));
+    }
+    // 平手・駒落ちの初期局面なら手合割だけを書き、それ以外は局面図を書く
+    let handicap = Handicap::ALL.iter().find(|h| {
+        record.initial_boards == create_handicap_board(**h) && record.initial_turn == h.first_turn()
+    });
+    if let Some(handicap) = handicap {
+        if record.header(HANDICAP_KEY).is_none() {
+            kif.push_str(&format!("{}：{}\n", HANDICAP_KEY, handicap.name()));
+        }
+    } else {
+        kif.push_str(&board_to_bod(&record.initial_boards, record.initial_turn));
+    }
+
+    kif.push_str(MOVE_HEADER);
+    kif.push('\n');
+    let mut boards = record.initial_boards;
+    let mut turn = record.initial_turn;
+    let mut prev_to = None;
+    let mut total = [0; 2];
+    for (i, m) in record.moves.iter().enumerate() {
+        let text = move_to_kif(&boards, turn, m, prev_to);
+        let time = record.times.get(i).copied().flatten().unwrap_or(0);
+        total[turn as usize] += time;
+        let t = total[turn as usize];
+        let padding = MOVE_WIDTH.saturating_sub(display_width(&text)).max(1);
+        kif.push_str(&format!(
+            "{:>4} {}{}({:>2}:{:02}/{:02}:{:02}:{:02})\n",
+            i + 1,
+            text,
+            " ".repeat(padding),
+            time / 60,
+            time % 60,
+            t / 3600,
+            t / 60 % 60,
+            t % 60
+        ));
+        boards = move_piece(boards, *m);
+        turn = turn.opponent();
+        prev_to = Some(m.to);
+    }
+
+    if let Some(termination) = record.termination {
+        let (word, _) = TERMINATIONS
+            .iter()
+            .find(|(_, t)| *t == termination)
+            .unwrap();
+        let count = record.moves.len();
+        kif.push_str(&format!("{:>4} {}\n", count + 1, word));
+        match termination.winner(turn) {
+            Some(winner) => {
+                kif.push_str(&format!("まで{}手で{}の勝ち\n", count, color_name(winner)))
+            }
+            None => kif.push_str(&format!("まで{}手で{}\n", count, word)),
+        }
+    }
+    kif
+}
+
+// 棋譜ファイルの中身を文字列にする関数
+// UTF-8として読めなければShift_JISとして読む
+pub fn decode_kif(bytes: &[u8]) -> Result<String> {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Ok(text.to_string());
+    }
+    let (text, _, had_errors) = encoding_rs::SHIFT_JIS.decode(bytes);
+    if had_errors {
+        bail!("kif is neither utf-8 nor shift_jis");
+    }
+    Ok(text.into_owned())
+}
+
+// KIF形式の文字列を読み込み、指し手を再生して棋譜に変換する関数
+// 変化手順は読み飛ばす
+pub fn parse_kif(kif: &str) -> Result<GameRecord> {
+    let mut headers = vec![];
+    let mut bod_rows: Vec<&str> = vec![];
+    let mut hands: [Option<&str>; 2] = [None, None];
+    let mut initial_turn = Color::Black;
+    let mut record: Option<GameRecord> = None;
+    let mut boards = Boards::empty();
+    let mut turn = Color::Black;
+    let mut prev_to = None;
+
+    for line in kif.lines() {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with('*')
+            || line.starts_with('&')
+            || line.starts_with("まで")
+            || line.starts_with("+---")
+            || line.starts_with('９')
+        {
+            continue;
+        }
+        if line.starts_with("変化") {
+            break;
+        }
+        if line.starts_with('|') {
+            bod_rows.push(line);
+            continue;
+        }
+        // 駒落ちの局面図では上手が後手、下手が先手
+        match line {
+            "先手番" | "下手番" => {
+                initial_turn = Color::Black;
+                continue;
+            }
+            "後手番" | "上手番" => {
+                initial_turn = Color::White;
+                continue;
+            }
+            _ => {}
+        }
+        if line.starts_with("手数") {
+            continue;
+        }
+
+        if line.starts_with(|c: char| c.is_ascii_digit()) {
+            // 最初の指し手で初期局面を確定させる
+            if record.is_none() {
+                let r = initial_record(headers.clone(), &bod_rows, hands, initial_turn)?;
+                boards = r.initial_boards;
+                turn = r.initial_turn;
+                record = Some(r);
+            }
+            let record = record.as_mut().unwrap();
+            let body = line.trim_start_matches(|c: char| c.is_ascii_digit()).trim();
+            if let Some((_, termination)) = TERMINATIONS.iter().find(|(w, _)| body.starts_with(w)) {
+                record.termination = Some(*termination);
+                break;
+            }
+            let (m, time) = parse_move(&boards, turn, body, prev_to)
+                .map_err(|e| anyhow!("{} (line: {})", e, line))?;
+            record.moves.push(m);
+            record.times.push(time);
+            boards = move_piece(boards, m);
+            turn = turn.opponent();
+            prev_to = Some(m.to);
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('：').or_else(|| line.split_once(':')) {
+            match key {
+                "先手の持駒" | "下手の持駒" => hands[Color::Black as usize] = Some(value),
+                "後手の持駒" | "上手の持駒" => hands[Color::White as usize] = Some(value),
+                _ => headers.push((key.to_string(), value.trim().to_string())),
+            }
+        }
+    }
+
+    match record {
+        Some(record) => Ok(record),
+        None => initial_record(headers, &bod_rows, hands, initial_turn),
+    }
+}
+
+// ヘッダと局面図から初期局面を作る
+fn initial_record(
+    headers: Vec<(String, String)>,
+    bod_rows: &[&str],
+    hands: [Option<&str>; 2],
+    turn: Color,
+) -> Result<GameRecord> {
+    let mut record = if bod_rows.is_empty() {
+        let name = headers
+            .iter()
+            .find(|(k, _)| k == HANDICAP_KEY)
+            .map_or(Handicap::Even.name(), |(_, v)| v.as_str());
+        let handicap =
+            Handicap::from_name(name).ok_or(anyhow!("unsupported handicap: {}", name))?;
+        GameRecord::new(create_handicap_board(handicap), handicap.first_turn())
+    } else {
+        GameRecord::new(parse_bod(bod_rows, hands)?, turn)
+    };
+    record.headers = headers;
+    Ok(record)
+}
+
+// 局面図(BOD)の盤面と持駒を読み込む
+fn parse_bod(rows: &[&str], hands: [Option<&str>; 2]) -> Result<Boards> {
+    if rows.len() != BOARD_SIZE {
+        bail!("invalid kif board rows: {}", rows.len());
+    }
+    let mut boards = Boards::empty();
+    for (rank, row) in rows.iter().enumerate() {
+        let y = BOARD_SIZE - 1 - rank;
+        let cells = row
+            .trim_start_matches('|')
+            .chars()
+            .take_while(|&c| c != '|')
+            .collect::<Vec<_>>();
+        if cells.len() != BOARD_SIZE * 2 {
+            bail!("invalid kif board row: {}", row);
+        }
+        for (file, cell) in cells.chunks(2).enumerate() {
+            let x = BOARD_SIZE - 1 - file;
+            if cell[1] == '・' {
+                continue;
+            }
+            let (piece_type, _) = piece_type_from_name(&cell[1].to_string())
+                .ok_or(anyhow!("invalid kif board piece: {}", row))?;
+            let color = if cell[0] == 'v' {
+                Color::White
+            } else {
+                Color::Black
+            };
+            boards.board[y][x] = Some(Piece::new(piece_type, color));
+        }
+    }
+    for color in [Color::Black, Color::White] {
+        let Some(hand) = hands[color as usize] else {
+            continue;
+        };
+        for token in hand.split(|c: char| c.is_whitespace()) {
+            if token.is_empty() || token == "なし" {
+                continue;
+            }
+            let (piece_type, len) =
+                piece_type_from_name(token).ok_or(anyhow!("invalid kif hand: {}", hand))?;
+            let count = match &token[len..] {
+                "" => 1,
+                n => parse_kanji_number(n).ok_or(anyhow!("invalid kif hand: {}", hand))?,
+            };
+            for _ in 0..count {
+                if !boards.hand.add(Piece::new(piece_type, color)) {
+                    bail!("invalid kif hand: {}", hand);
+                }
+            }
+        }
+    }
+    Ok(boards)
+}
+
+// 局面図(BOD)を書き出す
+fn board_to_bod(boards: &Boards, turn: Color) -> String {
+    let mut bod = format!("後手の持駒：{}\n", hand_to_kif(boards, Color::White));
+    bod.push_str("  ９ ８ ７ ６ ５ ４ ３ ２ １\n");
+    bod.push_str("+---------------------------+\n");
+    for y in (0..BOARD_SIZE).rev() {
+        bod.push('|');
+        for x in (0..BOARD_SIZE).rev() {
+            match boards.board[y][x] {
+                Some(piece) => {
+                    bod.push(if piece.color == Color::White {
+                        'v'
+                    } else {
+                        ' '
+                    });
+                    bod.push(board_piece_char(piece.piece_type));
+                }
+                None => bod.push_str(" ・"),
+            }
+        }
+        bod.push('|');
+        bod.push(RANK_CHARS[BOARD_SIZE - 1 - y]);
+        bod.push('\n');
+    }
+    bod.push_str("+---------------------------+\n");
+    bod.push_str(&format!(
+        "先手の持駒：{}\n",
+        hand_to_kif(boards, Color::Black)
+    ));
+    if turn == Color::White {
+        bod.push_str("後手番\n");
+    }
+    bod
+}
+
+fn hand_to_kif(boards: &Boards, color: Color) -> String {
+    let hand = HAND_PIECE_TYPES
+        .iter()
+        .filter_map(|&piece_type| match boards.hand.count(piece_type, color) {
+            0 => None,
+            1 => Some(piece_name(piece_type).to_string()),
+            n => Some(format!(
+                "{}{}",
+                piece_name(piece_type),
+                kanji_number(n as usize)
+            )),
+        })
+        .collect::<Vec<_>>();
+    if hand.is_empty() {
+        "なし".to_string()
+    } else {
+        hand.join("　")
+    }
+}
+
+// 指し手をKIFの表記(例: ７六歩(77), 同　角成(88), ５五角打)に変換する
+fn move_to_kif(
+    boards: &Boards,
+    turn: Color,
+    legal_move: &LegalMove,
+    prev_to: Option<Position>,
+) -> String {
+    let to = if prev_to == Some(legal_move.to) {
+        "同　".to_string()
+    } else {
+        square_to_kif(legal_move.to)
+    };
+    let from = legal_move.from;
+    let piece = legal_move.piece(boards).unwrap();
+    if from.z == 1 {
+        return format!("{}{}打", to, piece_name(piece.piece_type));
+    }
+    let revolute = if legal_move.revolute {
+        "成"
+    } else if piece.can_revolte() && legal_move.can_revolte(turn) {
+        "不成"
+    } else {
+        ""
+    };
+    format!(
+        "{}{}{}({}{})",
+        to,
+        piece_name(piece.piece_type),
+        revolute,
+        from.x + 1,
+        BOARD_SIZE as i32 - from.y
+    )
+}
+
+// KIFの指し手の部分を読み込み、指し手と消費時間(秒)を返す
+fn parse_move(
+    boards: &Boards,
+    turn: Color,
+    text: &str,
+    prev_to: Option<Position>,
+) -> Result<(LegalMove, Option<u32>)> {
+    let (to, rest) = match text.strip_prefix('同') {
+        Some(rest) => (
+            prev_to.ok_or(anyhow!("no previous move for 同"))?,
+            rest.trim_start(),
+        ),
+        None => {
+            let mut chars = text.chars();
+            let file = chars.next().and_then(parse_file);
+            let rank = chars.next().and_then(parse_rank);
+            let (Some(file), Some(rank)) = (file, rank) else {
+                bail!("invalid kif square: {}", text);
+            };
+            (
+                Position::new(file as i32 - 1, BOARD_SIZE as i32 - rank as i32, 0),
+                chars.as_str(),
+            )
+        }
+    };
+
+    let (piece_type, len) =
+        piece_type_from_name(rest).ok_or(anyhow!("invalid kif piece: {}", text))?;
+    let mut rest = &rest[len..];
+    let mut revolute = false;
+    let mut drop = false;
+    if let Some(r) = rest.strip_prefix("不成") {
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('成') {
+        revolute = true;
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('打') {
+        drop = true;
+        rest = r;
+    }
+
+    let legal_move = if drop || !rest.starts_with('(') {
+        find_drop_move(boards, turn, piece_type, to).ok_or(anyhow!("illegal kif drop: {}", text))?
+    } else {
+        let (from, r) = rest[1..]
+            .split_once(')')
+            .ok_or(anyhow!("invalid kif move: {}", text))?;
+        rest = r;
+        let digits = from
+            .chars()
+            .filter_map(|c| c.to_digit(10))
+            .collect::<Vec<_>>();
+        let &[file, rank] = digits.as_slice() else {
+            bail!("invalid kif move source: {}", text);
+        };
+        let from = Position::new(file as i32 - 1, BOARD_SIZE as i32 - rank as i32, 0);
+        if !from.is_valid() {
+            bail!("invalid kif move source: {}", text);
+        }
+        match boards.board[from.y as usize][from.x as usize] {
+            Some(p) if p.piece_type == piece_type && p.color == turn => {}
+            _ => bail!("kif piece does not match the board: {}", text),
+        }
+        let legal_move = LegalMove { from, to, revolute };
+        if !create_move_range(boards, turn).contains(&legal_move) {
+            bail!("illegal kif move: {}", text);
+        }
+        legal_move
+    };
+
+    // 消費時間 ( 0:16/00:00:16)
+    let time = rest
+        .trim()
+        .strip_prefix('(')
+        .and_then(|t| t.split('/').next())
+        .and_then(|t| t.trim().split_once(':'))
+        .and_then(|(m, s)| Some(m.trim().parse::<u32>().ok()? * 60 + s.parse::<u32>().ok()?));
+    Ok((legal_move, time))
+}
+
+fn square_to_kif(position: Position) -> String {
+    format!(
+        "{}{}",
+        FILE_CHARS[position.x as usize],
+        RANK_CHARS[BOARD_SIZE - 1 - position.y as usize]
+    )
+}
+
+fn parse_file(c: char) -> Option<u32> {
+    FILE_CHARS
+        .iter()
+        .position(|&f| f == c)
+        .map(|i| i as u32 + 1)
+        .or_else(|| c.to_digit(10).filter(|&d| d > 0))
+}
+
+fn parse_rank(c: char) -> Option<u32> {
+    RANK_CHARS
+        .iter()
+        .position(|&r| r == c)
+        .map(|i| i as u32 + 1)
+        .or_else(|| c.to_digit(10).filter(|&d| d > 0))
+}
+
+fn piece_name(piece_type: PieceType) -> &'static str {
+    PIECE_NAMES
+        .iter()
+        .find(|(_, t)| *t == piece_type)
+        .map(|(name, _)| *name)
+        .unwrap()
+}
+
+// 局面図で使う1文字の駒の名前
+fn board_piece_char(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::PromotedSilver => '全',
+        PieceType::PromotedKnight => '圭',
+        PieceType::PromotedLance => '杏',
+        _ => piece_name(piece_type).chars().next().unwrap(),
+    }
+}
+
+// 先頭にある駒の名前を読み、駒の種類と名前のバイト数を返す
+fn piece_type_from_name(text: &str) -> Option<(PieceType, usize)> {
+    PIECE_NAMES
+        .iter()
+        .find(|(name, _)| text.starts_with(name))
+        .map(|(name, t)| (*t, name.len()))
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::Black => "先手",
+        Color::White => "後手",
+    }
+}
+
+fn kanji_number(n: usize) -> String {
+    if n >= 10 {
+        format!("十{}", KANJI_NUMBERS[n - 10])
+    } else {
+        KANJI_NUMBERS[n].to_string()
+    }
+}
+
+fn parse_kanji_number(text: &str) -> Option<usize> {
+    let (tens, ones) = match text.strip_prefix('十') {
+        Some(ones) => (10, ones),
+        None => (0, text),
+    };
+    let ones = match ones {
+        "" => 0,
+        n => KANJI_NUMBERS
+            .iter()
+            .position(|&k| k == n)
+            .filter(|&i| i > 0)?,
+    };
+    Some(tens + ones).filter(|&n| n > 0)
+}
+
+// 全角文字を2として数えた表示幅
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sfen::{parse_sfen, INITIAL_SFEN},
+        usi::move_from_usi,
+    };
+
+    // 同・成・打・不成を含む手順
+    const MOVES: [&str; 7] = ["7g7f", "3c3d", "8h2b+", "3a2b", "B*4e", "6a5b", "4e6c"];
+
+    // SFENの局面からUSIの手を順に指した棋譜 (消費時間は1秒, 2秒, ...)
+    fn record(sfen: &str, moves: &[&str]) -> GameRecord {
+        let (mut boards, mut turn, _) = parse_sfen(sfen).unwrap();
+        let mut record = GameRecord::new(boards, turn);
+        for (i, usi) in moves.iter().enumerate() {
+            let m = move_from_usi(&boards, turn, usi).unwrap();
+            record.moves.push(m);
+            record.times.push(Some(i as u32 + 1));
+            boards = move_piece(boards, m);
+            turn = turn.opponent();
+        }
+        record
+    }
+
+    #[test]
+    fn export_moves_and_resign() {
+        let mut record = record(INITIAL_SFEN, &MOVES);
+        record.termination = Some(Termination::Resign);
+        let kif = to_kif(&record);
+        let lines = kif.lines().collect::<Vec<_>>();
+        assert!(lines.contains(&"手合割：平手"));
+        for expected in [
+            "   1 ７六歩(77)    ( 0:01/00:00:01)",
+            "   3 ２二角成(88)  ( 0:03/00:00:04)",
+            "   4 同　銀(31)    ( 0:04/00:00:06)",
+            "   5 ４五角打      ( 0:05/00:00:09)",
+            "   7 ６三角不成(45) ( 0:07/00:00:16)",
+            "   8 投了",
+            "まで7手で先手の勝ち",
+        ] {
+            assert!(lines.contains(&expected), "{}\n{}", expected, kif);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut record = record(INITIAL_SFEN, &MOVES);
+        record.headers = vec![(HANDICAP_KEY.to_string(), "平手".to_string())];
+        for termination in [Termination::Resign, Termination::Checkmate] {
+            record.termination = Some(termination);
+            assert_eq!(parse_kif(&to_kif(&record)).unwrap(), record);
+        }
+    }
+
+    #[test]
+    fn parse_moves_and_checkmate() {
+        let kif = "手合割：平手\n\
+            手数----指手---------消費時間--\n\
+            1 ７六歩(77)\n\
+            2 ３四歩(33)\n\
+            3 ２二角成(88)\n\
+            4 同銀(31)\n\
+            5 ４五角打\n\
+            6 ５二金(61)\n\
+            7 ６三角不成(45)\n\
+            8 詰み\n\
+            まで7手で先手の勝ち\n";
+        let parsed = parse_kif(kif).unwrap();
+        let expected = record(INITIAL_SFEN, &MOVES);
+        assert_eq!(parsed.initial_boards, expected.initial_boards);
+        assert_eq!(parsed.moves, expected.moves);
+        assert_eq!(parsed.times, vec![None; MOVES.len()]);
+        assert_eq!(parsed.termination, Some(Termination::Checkmate));
+        assert!(!parsed.moves[6].revolute);
+        assert!(parsed.moves[2].revolute);
+    }
+
+    #[test]
+    fn handicap_bod_with_uwate_to_move() {
+        let mut record = record("4k4/9/9/9/9/9/9/9/4K4 w G 1", &["5a5b"]);
+        record.termination = Some(Termination::Resign);
+        let kif = to_kif(&record)
+            .replace("後手番", "上手番")
+            .replace("先手の持駒", "下手の持駒")
+            .replace("後手の持駒", "上手の持駒");
+        assert!(kif.contains("上手番"));
+        let parsed = parse_kif(&kif).unwrap();
+        assert_eq!(parsed.initial_turn, Color::White);
+        assert_eq!(parsed.initial_boards, record.initial_boards);
+        assert_eq!(parsed.moves, record.moves);
+    }
+
+    #[test]
+    fn decode_shift_jis() {
+        let kif = "手合割：平手\n1 ７六歩(77)\n";
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode(kif);
+        assert_eq!(decode_kif(&sjis).unwrap(), kif);
+        assert_eq!(decode_kif(kif.as_bytes()).unwrap(), kif);
+        assert!(decode_kif(&[0x82, 0xff]).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use crate::{
+    bitboard::PIECE_TYPES,
+    board::{
+        create_initial_board, create_move_range, get_num_array, move_piece, BoardAsNum, Boards,
+        BOARD_SIZE, HAND_MAX_COUNTS, HAND_PIECE_TYPES, PAGE_SIZE,
+    },
+    piece::{Color, Piece, PieceType},
+};
+use anyhow::{anyhow, bail, Result};
+
+// 持ち駒を盤面の2ページ目の升に置いていた頃のRECORDSを今の形式に変換する
+//
+// 旧形式では持ち駒を駒の種類ごとに決まった升に置き、(駒の番号 + 14 * 手番) * 2 - 1 の面を1にしていた
+// 先手の持ち駒の面は盤上の駒の面と重なるため、1つ前の局面から指せる手を全て試して
+// 旧形式で同じになる局面を探して復元する
+
+// 1升あたりの面の数と1局面あたりのバイト数
+const CHANNEL_COUNT: usize = PieceType::get_max() as usize * PAGE_SIZE * 2;
+const PAGE_LEN: usize = BOARD_SIZE * BOARD_SIZE * CHANNEL_COUNT;
+
+// 1局面あたりに残す候補の最大数
+const MAX_CANDIDATES: usize = 64;
+
+// 旧形式で持ち駒を置いていた升 (置く順)
+fn hand_slots(piece_type: PieceType, color: Color) -> Vec<(usize, usize)> {
+    let row = |y: usize, xs: std::ops::Range<usize>| xs.map(move |x| (x, y));
+    let slots: Vec<(usize, usize)> = match piece_type {
+        PieceType::Pawn => row(0, 0..BOARD_SIZE).chain(row(1, 0..BOARD_SIZE)).collect(),
+        PieceType::Gold => row(2, 0..4).collect(),
+        PieceType::Silver => row(2, 4..8).collect(),
+        PieceType::Knight => row(3, 0..4).collect(),
+        PieceType::Lance => row(3, 4..8).collect(),
+        PieceType::Bishop => row(4, 0..2).collect(),
+        PieceType::Rook => row(4, 2..4).collect(),
+        _ => vec![],
+    };
+    match color {
+        Color::Black => slots,
+        // 後手は盤面を180度回した升に置いていた
+        Color::White => slots
+            .into_iter()
+            .map(|(x, y)| (BOARD_SIZE - 1 - x, BOARD_SIZE - 1 - y))
+            .collect(),
+    }
+}
+
+fn board_channel(piece: Piece) -> usize {
+    piece.get_u8() as usize + PieceType::get_max() as usize * piece.color as usize - 1
+}
+
+fn hand_channel(piece: Piece) -> usize {
+    (piece.get_u8() as usize + PieceType::get_max() as usize * piece.color as usize) * 2 - 1
+}
+
+// 局面を旧形式の配列に変換する関数
+pub fn get_legacy_num_array(boards: &Boards) -> BoardAsNum {
+    let mut b = get_num_array(boards);
+    b.iter_mut()
+        .flat_map(|col| col.iter_mut())
+        .for_each(|cell| cell[PieceType::get_max() as usize * 2..].fill(0));
+    for color in [Color::Black, Color::White] {
+        for piece_type in HAND_PIECE_TYPES {
+            let piece = Piece::new(piece_type, color);
+            let count = boards.hand.count(piece_type, color) as usize;
+            for &(x, y) in hand_slots(piece_type, color).iter().take(count) {
+                b[x][y][hand_channel(piece)] = 1;
+            }
+        }
+    }
+    b
+}
+
+// 1つ前の局面の候補から1手で旧形式の配列と同じになる局面を全て探す
+// 候補は(局面, 1つ前の候補の位置)の組
+fn find_successors(prevs: &[(Boards, usize)], old: &BoardAsNum) -> Vec<(Boards, usize)> {
+    let mut found: Vec<(Boards, usize)> = vec![];
+    for (parent, (prev, _)) in prevs.iter().enumerate() {
+        for turn in [Color::Black, Color::White] {
+            for m in create_move_range(prev, turn) {
+                let boards = move_piece(*prev, m);
+                if found.len() < MAX_CANDIDATES
+                    && !found.iter().any(|(b, _)| *b == boards)
+                    && get_legacy_num_array(&boards) == *old
+                {
+                    found.push((boards, parent));
+                }
+            }
+        }
+    }
+    found
+}
+
+// 前の局面が分からないときに旧形式の配列だけから局面を復元する関数
+// 先手の持ち駒は置く順に升が埋まっているので、先頭から続いている升の数を候補にして
+// 駒の枚数が揃う組み合わせを探す
+fn decode(old: &BoardAsNum) -> Result<Boards> {
+    let prefixes = HAND_PIECE_TYPES
+        .iter()
+        .map(|&piece_type| {
+            let channel = hand_channel(Piece::new(piece_type, Color::Black));
+            hand_slots(piece_type, Color::Black)
+                .iter()
+                .take_while(|&&(x, y)| old[x][y][channel] == 1)
+                .count()
+        })
+        .collect::<Vec<_>>();
+    let mut counts = vec![0; HAND_PIECE_TYPES.len()];
+    let mut fallback = None;
+    loop {
+        if let Some(boards) = decode_with_hand(old, &counts) {
+            let totals = piece_totals(&boards);
+            // 持ち駒の最大枚数はその駒の総数と同じ
+            if totals == HAND_MAX_COUNTS {
+                return Ok(boards);
+            }
+            // 駒落ちの局面は駒が足りないので、枚数を超えない組み合わせで代用する
+            if fallback.is_none() && totals.iter().zip(HAND_MAX_COUNTS).all(|(&n, max)| n <= max) {
+                fallback = Some(boards);
+            }
+        }
+        // 次の組み合わせ
+        let Some(i) = (0..counts.len()).find(|&i| counts[i] < prefixes[i]) else {
+            break;
+        };
+        counts[i] += 1;
+        counts[..i].fill(0);
+    }
+    fallback.ok_or(anyhow!("failed to decode legacy record"))
+}
+
+// 先手の持ち駒の枚数を決めて旧形式の配列を読む
+// 1つの升に盤上の駒が2つ以上残る場合はNoneを返す
+fn decode_with_hand(old: &BoardAsNum, black_counts: &[usize]) -> Option<Boards> {
+    let mut old = *old;
+    let mut boards = Boards::empty();
+    for color in [Color::Black, Color::White] {
+        for (i, &piece_type) in HAND_PIECE_TYPES.iter().enumerate() {
+            let piece = Piece::new(piece_type, color);
+            let channel = hand_channel(piece);
+            for (x, y) in hand_slots(piece_type, color) {
+                // 後手の持ち駒の面は盤上の駒と重ならない
+                let is_hand = match color {
+                    Color::Black => boards.hand.count(piece_type, color) < black_counts[i] as u8,
+                    Color::White => old[x][y][channel] == 1,
+                };
+                if !is_hand {
+                    break;
+                }
+                old[x][y][channel] = 0;
+                if !boards.hand.add(piece) {
+                    return None;
+                }
+            }
+        }
+    }
+    for (x, col) in old.iter().enumerate() {
+        for (y, cell) in col.iter().enumerate() {
+            let mut found = PIECE_TYPES
+                .iter()
+                .flat_map(|&t| [Piece::new(t, Color::Black), Piece::new(t, Color::White)])
+                .filter(|&p| cell[board_channel(p)] == 1);
+            boards.board[y][x] = found.next();
+            if found.next().is_some() {
+                return None;
+            }
+        }
+    }
+    Some(boards)
+}
+
+// 成り駒も含めて駒の種類ごとの枚数を数える (HAND_PIECE_TYPESの順)
+fn piece_totals(boards: &Boards) -> [u8; HAND_PIECE_TYPES.len()] {
+    std::array::from_fn(|i| {
+        let piece_type = HAND_PIECE_TYPES[i];
+        let on_board = boards
+            .board
+            .iter()
+            .flat_map(|row| row.iter())
+            .filter(|p| matches!(p, Some(p) if p.revolute_back().piece_type == piece_type))
+            .count() as u8;
+        on_board
+            + boards.hand.count(piece_type, Color::Black)
+            + boards.hand.count(piece_type, Color::White)
+    })
+}
+
+// 旧形式のRECORDSを今の形式に変換する関数
+// 旧形式では区別できない局面(持ち駒の升に相手の駒を打った場合など)があるので、
+// 候補を全て残して後の局面と続くものを選ぶ
+// 1局面目は平手の初期局面からの1手として探し、見つからなければ配列だけから復元する
+// 2局面目以降で1手前の局面から続く局面が見つからない場合は、手順がつながらないのでエラーにする
+pub fn convert_legacy_records(records: &[u8]) -> Result<Vec<u8>> {
+    let chunks = records.chunks_exact(PAGE_LEN);
+    if !chunks.remainder().is_empty() {
+        bail!("invalid legacy record length: {}", records.len());
+    }
+    let mut layers = vec![vec![(create_initial_board(), 0)]];
+    for (i, chunk) in chunks.enumerate() {
+        let mut old: BoardAsNum = [[[0; CHANNEL_COUNT]; BOARD_SIZE]; BOARD_SIZE];
+        old.iter_mut()
+            .flat_map(|col| col.iter_mut())
+            .zip(chunk.chunks(CHANNEL_COUNT))
+            .for_each(|(cell, data)| cell.copy_from_slice(data));
+        let mut candidates = find_successors(layers.last().unwrap(), &old);
+        if candidates.is_empty() {
+            if i > 0 {
+                bail!("no move leads to position {} of legacy record", i + 1);
+            }
+            candidates.push((decode(&old)?, 0));
+        }
+        layers.push(candidates);
+    }
+
+    // 最後の局面から1つ前の候補をたどる
+    let mut index = 0;
+    let mut boards_record = vec![];
+    for layer in layers.iter().skip(1).rev() {
+        let (boards, parent) = layer[index];
+        boards_record.push(boards);
+        index = parent;
+    }
+    Ok(boards_record
+        .iter()
+        .rev()
+        .flat_map(|boards| get_num_array(boards).concat().concat())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bitboard::Position, sfen::parse_sfen};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn legacy_records(boards_record: &[Boards]) -> Vec<u8> {
+        boards_record
+            .iter()
+            .flat_map(|boards| get_legacy_num_array(boards).concat().concat())
+            .collect()
+    }
+
+    fn records(boards_record: &[Boards]) -> Vec<u8> {
+        boards_record
+            .iter()
+            .flat_map(|boards| get_num_array(boards).concat().concat())
+            .collect()
+    }
+
+    // 平手の初期局面からランダムに指した対局の各局面
+    fn random_game(seed: u64, plies: usize) -> Vec<Boards> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut position = Position::from_boards(&create_initial_board(), Color::Black);
+        let mut boards_record = vec![];
+        for _ in 0..plies {
+            let moves = position.legal_moves();
+            if moves.is_empty() {
+                break;
+            }
+            position = position.move_piece(&moves[rng.gen_range(0..moves.len())]);
+            boards_record.push(position.to_boards());
+        }
+        boards_record
+    }
+
+    #[test]
+    fn hand_encoding() {
+        let (boards, _, _) = parse_sfen("4k4/9/9/9/9/9/9/9/4K4 b 2GS3p 1").unwrap();
+        let pawn = Piece::new(PieceType::Pawn, Color::White);
+        let gold = Piece::new(PieceType::Gold, Color::Black);
+        let silver = Piece::new(PieceType::Silver, Color::Black);
+
+        // 旧形式: 駒の種類ごとに決まった升 (後手は180度回した升) に置く
+        let old = get_legacy_num_array(&boards);
+        assert_eq!(old[0][2][hand_channel(gold)], 1);
+        assert_eq!(old[1][2][hand_channel(gold)], 1);
+        assert_eq!(old[2][2][hand_channel(gold)], 0);
+        assert_eq!(old[4][2][hand_channel(silver)], 1);
+        assert_eq!(old[8][8][hand_channel(pawn)], 1);
+        assert_eq!(old[6][8][hand_channel(pawn)], 1);
+        assert_eq!(old[5][8][hand_channel(pawn)], 0);
+
+        // 今の形式: 後半の面に枚数分の升を先頭から1にする
+        let new = get_num_array(&boards);
+        let hand_plane = |piece: Piece| PieceType::get_max() as usize * 2 + board_channel(piece);
+        assert_eq!(new[0][0][hand_plane(gold)], 1);
+        assert_eq!(new[1][0][hand_plane(gold)], 1);
+        assert_eq!(new[2][0][hand_plane(gold)], 0);
+        assert_eq!(new[0][0][hand_plane(silver)], 1);
+        assert_eq!(new[2][0][hand_plane(pawn)], 1);
+        assert_eq!(new[3][0][hand_plane(pawn)], 0);
+    }
+
+    #[test]
+    fn convert_game() {
+        for seed in 0..4 {
+            let boards_record = random_game(seed, 80);
+            let converted = convert_legacy_records(&legacy_records(&boards_record)).unwrap();
+            assert_eq!(converted, records(&boards_record), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn decode_without_previous_position() {
+        let boards_record = random_game(5, 60);
+        for boards in boards_record.iter() {
+            assert_eq!(decode(&get_legacy_num_array(boards)).unwrap(), *boards);
+        }
+    }
+
+    #[test]
+    fn broken_record_is_rejected() {
+        // 途中の局面が抜けていて1手でつながらない
+        let mut boards_record = random_game(6, 20);
+        boards_record.remove(10);
+        assert!(convert_legacy_records(&legacy_records(&boards_record)).is_err());
+        // 長さが局面の大きさの倍数でない
+        let records = legacy_records(&boards_record[..2]);
+        assert!(convert_legacy_records(&records[1..]).is_err());
+    }
+}
@@ -1,5 +1,20 @@
+pub mod bitboard;
 pub mod board;
+pub mod csa;
 pub mod db;
+pub mod eval;
 pub mod game;
 pub mod inference;
+pub mod kif;
+pub mod legacy;
+pub mod mcts;
+pub mod perft;
 pub mod piece;
+pub mod record;
+pub mod search;
+pub mod sfen;
+pub mod tsume;
+pub mod tt;
+pub mod usi;
+pub mod validate;
+pub mod zobrist;
@@ -0,0 +1,457 @@
+use crate::{
+    bitboard::{Move, Position},
+    board::{Boards, LegalMove},
+    piece::Color,
+};
+use anyhow::Result;
+use rand::Rng;
+
+// 局面の勝率をまとめて評価する (手番の側が勝つ確率)
+pub trait BatchEvaluator {
+    fn evaluate_batch(&self, positions: &[Position]) -> Result<Vec<f32>>;
+}
+
+// 自己対局でのモンテカルロ木探索の設定
+#[derive(Debug, Clone, Copy)]
+pub struct MctsConfig {
+    // 1手あたりのシミュレーション回数
+    pub simulations: usize,
+    // まとめて推論する末端の局面の数
+    pub batch_size: usize,
+    // PUCTの探索項の係数
+    pub c_puct: f32,
+    // ルートの事前確率に混ぜるディリクレノイズ
+    pub dirichlet_alpha: f32,
+    pub dirichlet_fraction: f32,
+    // 探索中の局面を負けとして数える回数 (同じ局面ばかり選ばないようにする)
+    pub virtual_loss: f32,
+    // 訪問回数から指し手を選ぶときの温度 (0なら訪問回数が最大の手)
+    pub temperature: f32,
+    // この手数までは温度を使い、以降は訪問回数が最大の手を選ぶ
+    pub temperature_moves: usize,
+    // 1手詰めがないときに探索前に詰みを探すノード数の上限 (0なら探さない)
+    pub mate_search_nodes: u64,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        MctsConfig {
+            simulations: 400,
+            batch_size: 16,
+            c_puct: 1.5,
+            dirichlet_alpha: 0.15,
+            dirichlet_fraction: 0.25,
+            virtual_loss: 1.0,
+            temperature: 1.0,
+            temperature_moves: 30,
+            mate_search_nodes: 2_000,
+        }
+    }
+}
+
+// 探索木のノード
+// 評価値はこのノードに指した側(親の手番)から見た値
+struct Node {
+    parent: Option<usize>,
+    // 親の局面から指した手 (ルートはNone)
+    m: Option<Move>,
+    // 初めて選ばれたときに作る
+    position: Option<Position>,
+    prior: f32,
+    visits: u32,
+    value_sum: f32,
+    virtual_visits: u32,
+    children: Vec<usize>,
+    expanded: bool,
+}
+
+impl Node {
+    fn new(parent: Option<usize>, m: Option<Move>, prior: f32) -> Node {
+        Node {
+            parent,
+            m,
+            position: None,
+            prior,
+            visits: 0,
+            value_sum: 0.0,
+            virtual_visits: 0,
+            children: vec![],
+            expanded: false,
+        }
+    }
+}
+
+struct Tree {
+    nodes: Vec<Node>,
+    config: MctsConfig,
+}
+
+impl Tree {
+    // 指せる手を子ノードにする (方策がないので事前確率は一様)
+    // 指せる手がなければfalseを返す
+    fn expand(&mut self, index: usize) -> bool {
+        let moves = self.nodes[index].position.unwrap().legal_moves();
+        let prior = 1.0 / moves.len().max(1) as f32;
+        for m in moves {
+            let child = self.nodes.len();
+            self.nodes.push(Node::new(Some(index), Some(m), prior));
+            self.nodes[index].children.push(child);
+        }
+        self.nodes[index].expanded = true;
+        !self.nodes[index].children.is_empty()
+    }
+
+    // 探索中の訪問は勝率0の訪問として数えた訪問回数
+    fn visits(&self, node: &Node) -> f32 {
+        node.visits as f32 + node.virtual_visits as f32 * self.config.virtual_loss
+    }
+
+    // PUCTが最大の子ノードを選ぶ
+    fn select_child(&self, index: usize) -> usize {
+        let node = &self.nodes[index];
+        let parent_visits = self.visits(node);
+        let score = |&child: &usize| {
+            let child = &self.nodes[child];
+            let visits = self.visits(child);
+            // 訪問していない手は引き分け(勝率0.5)として扱う
+            let q = if visits > 0.0 {
+                child.value_sum / visits
+            } else {
+                0.5
+            };
+            q + self.config.c_puct * child.prior * parent_visits.sqrt() / (1.0 + visits)
+        };
+        *node
+            .children
+            .iter()
+            .max_by(|a, b| score(a).total_cmp(&score(b)))
+            .unwrap()
+    }
+
+    // ルートから末端まで降りて、通ったノードに仮想損失を加える
+    fn select_leaf(&mut self) -> usize {
+        let mut index = 0;
+        loop {
+            self.nodes[index].virtual_visits += 1;
+            if !self.nodes[index].expanded || self.nodes[index].children.is_empty() {
+                break;
+            }
+            let child = self.select_child(index);
+            if self.nodes[child].position.is_none() {
+                let parent = self.nodes[index].position.unwrap();
+                let m = self.nodes[child].m.unwrap();
+                self.nodes[child].position = Some(parent.move_piece(&m));
+            }
+            index = child;
+        }
+        index
+    }
+
+    // 末端の手番から見た勝率を親へ伝える
+    fn backup(&mut self, leaf: usize, value: f32) {
+        let mut value = 1.0 - value;
+        let mut index = Some(leaf);
+        while let Some(i) = index {
+            let node = &mut self.nodes[i];
+            node.virtual_visits -= 1;
+            node.visits += 1;
+            node.value_sum += value;
+            value = 1.0 - value;
+            index = node.parent;
+        }
+    }
+
+    // 選んだ末端を評価せずに仮想損失だけ取り消す
+    fn cancel(&mut self, leaf: usize) {
+        let mut index = Some(leaf);
+        while let Some(i) = index {
+            self.nodes[i].virtual_visits -= 1;
+            index = self.nodes[i].parent;
+        }
+    }
+
+    fn add_dirichlet_noise(&mut self, rng: &mut impl Rng) {
+        let children = self.nodes[0].children.clone();
+        let noise = children
+            .iter()
+            .map(|_| sample_gamma(self.config.dirichlet_alpha, rng))
+            .collect::<Vec<_>>();
+        let total = noise.iter().sum::<f32>();
+        if total <= 0.0 {
+            return;
+        }
+        let fraction = self.config.dirichlet_fraction;
+        for (child, n) in children.into_iter().zip(noise) {
+            let prior = &mut self.nodes[child].prior;
+            *prior = *prior * (1.0 - fraction) + n / total * fraction;
+        }
+    }
+}
+
+// ルートの局面から探索し、指せる手ごとの訪問回数を返す関数
+// 指せる手がない場合は空になる
+pub fn search<E: BatchEvaluator>(
+    boards: &Boards,
+    turn: Color,
+    evaluator: &E,
+    config: &MctsConfig,
+    rng: &mut impl Rng,
+) -> Result<Vec<(LegalMove, u32)>> {
+    let mut root = Node::new(None, None, 1.0);
+    root.position = Some(Position::from_boards(boards, turn));
+    let mut tree = Tree {
+        nodes: vec![root],
+        config: *config,
+    };
+    if !tree.expand(0) {
+        return Ok(vec![]);
+    }
+    tree.add_dirichlet_noise(rng);
+
+    let mut simulations = 0;
+    while simulations < config.simulations {
+        // 仮想損失を使って異なる末端を集め、まとめて推論する
+        let mut leaves = vec![];
+        let mut terminals = vec![];
+        while leaves.len() + terminals.len() < config.batch_size.max(1)
+            && simulations + leaves.len() + terminals.len() < config.simulations
+        {
+            let leaf = tree.select_leaf();
+            if tree.nodes[leaf].expanded {
+                // 指せる手がない局面は手番の側の負け
+                terminals.push(leaf);
+            } else if leaves.contains(&leaf) {
+                // 同じ末端を選んだらそこで打ち切る
+                tree.cancel(leaf);
+                break;
+            } else {
+                leaves.push(leaf);
+            }
+        }
+        let positions = leaves
+            .iter()
+            .map(|&leaf| tree.nodes[leaf].position.unwrap())
+            .collect::<Vec<_>>();
+        let values = if positions.is_empty() {
+            vec![]
+        } else {
+            evaluator.evaluate_batch(&positions)?
+        };
+        for (&leaf, value) in leaves.iter().zip(values) {
+            // 詰んでいる局面は推論の値を使わない
+            let value = if tree.expand(leaf) { value } else { 0.0 };
+            tree.backup(leaf, value);
+        }
+        for &leaf in terminals.iter() {
+            tree.backup(leaf, 0.0);
+        }
+        simulations += leaves.len() + terminals.len();
+    }
+
+    Ok(tree.nodes[0]
+        .children
+        .iter()
+        .map(|&child| {
+            let node = &tree.nodes[child];
+            (node.m.unwrap().to_legal_move(turn), node.visits)
+        })
+        .collect())
+}
+
+// 訪問回数を温度で調整した確率で指し手を選ぶ関数
+// 温度が0なら訪問回数が最大の手を選ぶ
+pub fn select_move(
+    visits: &[(LegalMove, u32)],
+    temperature: f32,
+    rng: &mut impl Rng,
+) -> Option<LegalMove> {
+    if temperature <= 0.0 {
+        return visits.iter().max_by_key(|(_, n)| *n).map(|(m, _)| *m);
+    }
+    let weights = visits
+        .iter()
+        .map(|(_, n)| (*n as f64).powf(1.0 / temperature as f64))
+        .collect::<Vec<_>>();
+    let total = weights.iter().sum::<f64>();
+    if total <= 0.0 {
+        return visits.first().map(|(m, _)| *m);
+    }
+    let mut r = rng.gen_range(0.0..total);
+    for ((m, _), w) in visits.iter().zip(weights) {
+        if r < w {
+            return Some(*m);
+        }
+        r -= w;
+    }
+    visits.last().map(|(m, _)| *m)
+}
+
+// ガンマ分布からの標本 (Marsaglia-Tsangの方法)
+// ディリクレ分布はガンマ分布の標本を合計で割って作る
+fn sample_gamma(alpha: f32, rng: &mut impl Rng) -> f32 {
+    if alpha < 1.0 {
+        let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+        return sample_gamma(alpha + 1.0, rng) * u.powf(1.0 / alpha);
+    }
+    let d = alpha - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = sample_normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
+}
+
+// 標準正規分布からの標本 (Box-Muller法)
+fn sample_normal(rng: &mut impl Rng) -> f32 {
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.gen_range(0.0..1.0);
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sfen::{parse_sfen, INITIAL_SFEN},
+        usi::move_to_usi,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // どの局面も五分と評価する
+    struct Even;
+
+    impl BatchEvaluator for Even {
+        fn evaluate_batch(&self, positions: &[Position]) -> Result<Vec<f32>> {
+            Ok(vec![0.5; positions.len()])
+        }
+    }
+
+    // ルート -> 子 -> 孫の一本道の木 (局面は使わないので全部初期局面にしておく)
+    fn chain() -> Tree {
+        let (boards, turn, _) = parse_sfen(INITIAL_SFEN).unwrap();
+        let position = Position::from_boards(&boards, turn);
+        let mut nodes = vec![Node::new(None, None, 1.0)];
+        for i in 0..2 {
+            nodes.push(Node::new(Some(i), None, 1.0));
+            nodes[i].children.push(i + 1);
+            nodes[i].expanded = true;
+        }
+        for node in nodes.iter_mut() {
+            node.position = Some(position);
+        }
+        Tree {
+            nodes,
+            config: MctsConfig::default(),
+        }
+    }
+
+    #[test]
+    fn backup_alternates_sign() {
+        let mut tree = chain();
+        for node in tree.nodes.iter_mut() {
+            node.virtual_visits = 1;
+        }
+        // 孫の局面の手番の側の勝率が0.8
+        tree.backup(2, 0.8);
+        let values = tree.nodes.iter().map(|n| n.value_sum).collect::<Vec<_>>();
+        assert!((values[2] - 0.2).abs() < 1e-6);
+        assert!((values[1] - 0.8).abs() < 1e-6);
+        assert!((values[0] - 0.2).abs() < 1e-6);
+        for node in tree.nodes.iter() {
+            assert_eq!(node.visits, 1);
+            assert_eq!(node.virtual_visits, 0);
+        }
+    }
+
+    #[test]
+    fn cancel_removes_virtual_visits() {
+        let mut tree = chain();
+        let leaf = tree.select_leaf();
+        assert_eq!(leaf, 2);
+        assert!(tree.nodes.iter().all(|n| n.virtual_visits == 1));
+        tree.cancel(leaf);
+        assert!(tree
+            .nodes
+            .iter()
+            .all(|n| n.virtual_visits == 0 && n.visits == 0));
+    }
+
+    #[test]
+    fn virtual_visit_counts_as_loss_once() {
+        let mut nodes = vec![Node::new(None, None, 1.0)];
+        nodes[0].expanded = true;
+        nodes[0].visits = 3;
+        // 探索中の訪問を含めて勝率0.5と0.4の子
+        let mut in_flight = Node::new(Some(0), None, 0.5);
+        in_flight.visits = 1;
+        in_flight.value_sum = 1.0;
+        in_flight.virtual_visits = 1;
+        let mut visited = Node::new(Some(0), None, 0.5);
+        visited.visits = 2;
+        visited.value_sum = 0.8;
+        nodes.push(in_flight);
+        nodes.push(visited);
+        nodes[0].children = vec![1, 2];
+        let tree = Tree {
+            nodes,
+            config: MctsConfig::default(),
+        };
+        assert_eq!(tree.select_child(0), 1);
+    }
+
+    #[test]
+    fn in_flight_child_is_avoided() {
+        let mut nodes = vec![Node::new(None, None, 1.0)];
+        nodes[0].expanded = true;
+        for _ in 0..2 {
+            let mut child = Node::new(Some(0), None, 0.5);
+            child.visits = 1;
+            child.value_sum = 0.5;
+            nodes.push(child);
+        }
+        nodes[0].children = vec![1, 2];
+        nodes[0].visits = 2;
+        let mut tree = Tree {
+            nodes,
+            config: MctsConfig::default(),
+        };
+        tree.nodes[1].virtual_visits = 1;
+        assert_eq!(tree.select_child(0), 2);
+        tree.nodes[1].virtual_visits = 0;
+        tree.nodes[2].virtual_visits = 1;
+        assert_eq!(tree.select_child(0), 1);
+    }
+
+    #[test]
+    fn finds_mate_in_1() {
+        // 詰みの局面は推論せずに負けとして扱うので、詰ませる手に訪問が集まる
+        let (boards, turn, _) = parse_sfen("4k4/9/4P4/9/9/9/9/9/4K4 b G 1").unwrap();
+        let config = MctsConfig {
+            simulations: 800,
+            dirichlet_fraction: 0.0,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let visits = search(&boards, turn, &Even, &config, &mut rng).unwrap();
+        let best = select_move(&visits, 0.0, &mut rng).unwrap();
+        assert_eq!(move_to_usi(&best), "G*5b");
+        let total = visits.iter().map(|(_, n)| n).sum::<u32>();
+        assert_eq!(total as usize, config.simulations);
+    }
+
+    #[test]
+    fn no_legal_moves() {
+        // 詰んでいる局面では指せる手がない
+        let (boards, turn, _) = parse_sfen("4k4/4G4/4P4/9/9/9/9/9/4K4 w - 1").unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let visits = search(&boards, turn, &Even, &MctsConfig::default(), &mut rng).unwrap();
+        assert!(visits.is_empty());
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{
+    board::{create_legal_moves, move_piece, Boards, LegalMove},
+    piece::Color,
+};
+use rayon::prelude::*;
+
+// 指定した深さまでに現れる局面の数を数える関数 (指し手生成の検証用)
+pub fn perft(boards: &Boards, turn: Color, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = create_legal_moves(boards, turn);
+    // 最後の1手は局面を作らずに数えるだけでよい
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    moves
+        .par_iter()
+        .map(|m| perft(&move_piece(*boards, *m), turn.opponent(), depth - 1))
+        .sum()
+}
+
+// 最初の1手ごとにperftの結果を返す関数
+pub fn perft_divide(boards: &Boards, turn: Color, depth: u32) -> Vec<(LegalMove, u64)> {
+    if depth == 0 {
+        return vec![];
+    }
+    create_legal_moves(boards, turn)
+        .par_iter()
+        .map(|m| {
+            let nodes = perft(&move_piece(*boards, *m), turn.opponent(), depth - 1);
+            (*m, nodes)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfen::{parse_sfen, INITIAL_SFEN};
+
+    // 持ち駒と成りが多い局面 (いわゆる「まつり」局面)
+    const MATSURI_SFEN: &str =
+        "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1";
+    // 合法手が最も多い局面として知られる局面
+    const MAX_MOVES_SFEN: &str = "R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1";
+
+    fn perft_sfen(sfen: &str, depth: u32) -> u64 {
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        perft(&boards, turn, depth)
+    }
+
+    #[test]
+    fn initial_position() {
+        assert_eq!(perft_sfen(INITIAL_SFEN, 1), 30);
+        assert_eq!(perft_sfen(INITIAL_SFEN, 2), 900);
+        assert_eq!(perft_sfen(INITIAL_SFEN, 3), 25470);
+    }
+
+    #[test]
+    #[ignore]
+    fn initial_position_depth4() {
+        assert_eq!(perft_sfen(INITIAL_SFEN, 4), 719731);
+    }
+
+    #[test]
+    fn positions_with_drops_and_promotions() {
+        assert_eq!(perft_sfen(MATSURI_SFEN, 1), 207);
+        assert_eq!(perft_sfen(MATSURI_SFEN, 2), 28684);
+        assert_eq!(perft_sfen(MAX_MOVES_SFEN, 1), 593);
+    }
+
+    #[test]
+    #[ignore]
+    fn matsuri_depth3() {
+        assert_eq!(perft_sfen(MATSURI_SFEN, 3), 4809015);
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let (boards, turn, _) = parse_sfen(MATSURI_SFEN).unwrap();
+        let divide = perft_divide(&boards, turn, 2);
+        assert_eq!(divide.len(), 207);
+        assert_eq!(divide.iter().map(|(_, n)| n).sum::<u64>(), 28684);
+    }
+}
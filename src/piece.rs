@@ -311,18 +311,6 @@ impl Piece {
             move_range.push(rm);
         }
     }
-
-    pub fn can_capture_king(
-        &self,
-        position: Position,
-        board: &Board,
-        king_position: Position,
-    ) -> bool {
-        let move_range = self.create_move_range(position, board);
-        move_range
-            .par_iter()
-            .any(|p| p.to.x == king_position.x && p.to.y == king_position.y)
-    }
 }
 
 const fn calc_delta(v: i32) -> i32 {
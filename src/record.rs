@@ -0,0 +1,96 @@
+use crate::{
+    board::{move_piece, Boards, LegalMove},
+    piece::Color,
+};
+
+// 対局の終わり方
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Termination {
+    // 投了
+    Resign,
+    // 詰み
+    Checkmate,
+    // 時間切れ
+    TimeUp,
+    // 千日手
+    Sennichite,
+    // 持将棋
+    Jishogi,
+    // 入玉宣言勝ち
+    EnteringKing,
+    // 手番側の反則勝ち (直前の指し手が反則)
+    IllegalWin,
+    // 手番側の反則負け
+    IllegalLoss,
+    // 中断
+    Interrupt,
+}
+
+impl Termination {
+    // 終局時に手番だった側から勝者を求める
+    pub fn winner(&self, turn: Color) -> Option<Color> {
+        match self {
+            Termination::Resign
+            | Termination::Checkmate
+            | Termination::TimeUp
+            | Termination::IllegalLoss => Some(turn.opponent()),
+            Termination::EnteringKing | Termination::IllegalWin => Some(turn),
+            Termination::Sennichite | Termination::Jishogi | Termination::Interrupt => None,
+        }
+    }
+}
+
+// 棋譜ファイルの形式によらない対局の記録
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameRecord {
+    // 対局者名などのヘッダ (キー, 値)
+    pub headers: Vec<(String, String)>,
+    pub initial_boards: Boards,
+    pub initial_turn: Color,
+    pub moves: Vec<LegalMove>,
+    // 各指し手の消費時間(秒)
+    pub times: Vec<Option<u32>>,
+    pub termination: Option<Termination>,
+}
+
+impl GameRecord {
+    pub fn new(initial_boards: Boards, initial_turn: Color) -> Self {
+        GameRecord {
+            headers: vec![],
+            initial_boards,
+            initial_turn,
+            moves: vec![],
+            times: vec![],
+            termination: None,
+        }
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // 各指し手の後の盤面を初期局面から順に再現する
+    pub fn boards_record(&self) -> Vec<Boards> {
+        self.moves
+            .iter()
+            .scan(self.initial_boards, |boards, m| {
+                *boards = move_piece(*boards, *m);
+                Some(*boards)
+            })
+            .collect()
+    }
+
+    // 最終局面で手番の側
+    pub fn last_turn(&self) -> Color {
+        self.moves
+            .iter()
+            .fold(self.initial_turn, |turn, _| turn.opponent())
+    }
+
+    pub fn winner(&self) -> Option<Color> {
+        self.termination.and_then(|t| t.winner(self.last_turn()))
+    }
+}
@@ -0,0 +1,600 @@
+use crate::{
+    bitboard::{Move, Position, SQUARE_COUNT},
+    board::{hand_index, Boards, LegalMove, HAND_PIECE_TYPES},
+    eval::piece_value,
+    piece::Color,
+    tt::{Bound, TranspositionTable, TtEntry},
+};
+use anyhow::Result;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+// 評価値の範囲 (詰みはMATEから詰むまでの手数を引いた値)
+pub const INFINITE: i32 = 32000;
+pub const MATE: i32 = 30000;
+// 探索する深さの上限
+pub const MAX_DEPTH: u32 = 64;
+// アスピレーションウィンドウの幅
+const ASPIRATION_WINDOW: i32 = 200;
+// 時間切れを確認するノード数の間隔 (2の累乗)
+const CHECK_INTERVAL: u64 = 256;
+
+// 局面の評価 (手番の側から見た値、大きいほど手番の側が有利)
+pub trait Evaluator {
+    fn evaluate(&self, position: &Position) -> Result<i32>;
+}
+
+// 探索の制限 (深さと時間のどちらかに達するか、止める指示があったら終わる)
+#[derive(Debug, Clone)]
+pub struct SearchLimits {
+    pub depth: u32,
+    pub time: Option<Duration>,
+    // 静止探索の最初の手で王手も読むかどうか
+    pub quiescence_checks: bool,
+    // 他のスレッドからtrueにすると探索を打ち切る (USIのstop用)
+    pub stop: Option<Arc<AtomicBool>>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits {
+            depth: MAX_DEPTH,
+            time: None,
+            quiescence_checks: false,
+            stop: None,
+        }
+    }
+}
+
+// 反復深化の各深さの探索結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    // 指せる手がない場合はNone
+    pub best_move: Option<LegalMove>,
+    pub score: i32,
+    pub depth: u32,
+    // 読み筋 (最善手から順)
+    pub pv: Vec<LegalMove>,
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+// 反復深化で探索し、最後に探索しきった深さの結果を返す関数
+// 深さごとの結果はon_iterationで受け取れる (USIのinfoの出力用)
+// 置換表は次の探索や他のスレッドの探索と共有できる
+pub fn search<E: Evaluator>(
+    boards: &Boards,
+    turn: Color,
+    evaluator: &E,
+    tt: &TranspositionTable,
+    limits: SearchLimits,
+    mut on_iteration: impl FnMut(&SearchResult),
+) -> Result<SearchResult> {
+    let position = Position::from_boards(boards, turn);
+    let max_depth = limits.depth.min(MAX_DEPTH);
+    let mut searcher = Searcher::new(evaluator, tt, limits);
+    let mut result = SearchResult {
+        best_move: None,
+        score: -MATE,
+        depth: 0,
+        pv: vec![],
+        nodes: 0,
+        time: Duration::ZERO,
+    };
+    let moves = position.legal_moves();
+    if moves.is_empty() {
+        return Ok(result);
+    }
+
+    for depth in 1..=max_depth {
+        // 止める指示が来ていたら次の深さは読まない
+        let stop = searcher.limits.stop.as_ref();
+        if stop.is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+            break;
+        }
+        // 前の深さの評価値の周りの狭い窓で探索し、外れたら窓を広げて探索し直す
+        let (mut alpha, mut beta) = if depth >= 3 {
+            (
+                result.score - ASPIRATION_WINDOW,
+                result.score + ASPIRATION_WINDOW,
+            )
+        } else {
+            (-INFINITE, INFINITE)
+        };
+        let score = loop {
+            let score = searcher.alpha_beta(&position, depth as i32, alpha, beta, 0)?;
+            if searcher.stopped {
+                break score;
+            }
+            if score <= alpha {
+                alpha = -INFINITE;
+            } else if score >= beta {
+                beta = INFINITE;
+            } else {
+                break score;
+            }
+        };
+        // 途中で打ち切った深さの結果は使わない
+        if searcher.stopped {
+            break;
+        }
+        searcher.prev_pv = searcher.pv[0].clone();
+        let pv = searcher
+            .prev_pv
+            .iter()
+            .scan(turn, |turn, m| {
+                let legal_move = m.to_legal_move(*turn);
+                *turn = turn.opponent();
+                Some(legal_move)
+            })
+            .collect::<Vec<_>>();
+        result = SearchResult {
+            best_move: pv.first().copied(),
+            score,
+            depth,
+            pv,
+            nodes: searcher.nodes,
+            time: searcher.start.elapsed(),
+        };
+        on_iteration(&result);
+        // 詰みが見つかったらそれ以上深く読まない
+        if score.abs() >= MATE - MAX_DEPTH as i32 {
+            break;
+        }
+    }
+    // 1つの深さも読み終わらないうちに時間切れになった場合は、
+    // 読み終えたルートの手の中で一番良い手 (1手も読み終えていなければ最初の手) を指す
+    if result.best_move.is_none() {
+        let (m, score) = searcher.root_best.unwrap_or((moves[0], 0));
+        result.best_move = Some(m.to_legal_move(turn));
+        result.pv = result.best_move.into_iter().collect();
+        result.score = score;
+        result.nodes = searcher.nodes;
+        result.time = searcher.start.elapsed();
+    }
+    Ok(result)
+}
+
+struct Searcher<'a, E: Evaluator> {
+    evaluator: &'a E,
+    tt: &'a TranspositionTable,
+    limits: SearchLimits,
+    start: Instant,
+    nodes: u64,
+    stopped: bool,
+    // 手数ごとの読み筋
+    pv: Vec<Vec<Move>>,
+    // 1つ浅い深さで探索したときの読み筋
+    prev_pv: Vec<Move>,
+    // 今の深さで読み終えたルートの手の中で一番良い手と評価値 (途中で時間切れになったとき用)
+    root_best: Option<(Move, i32)>,
+    // 手数ごとにβカットを起こした駒を取らない手 (2手まで)
+    killers: Vec<[Option<Move>; 2]>,
+    // βカットを起こした手の履歴 [移動元(打つ手は81+持ち駒の種類)][移動先]
+    history: Vec<[i32; SQUARE_COUNT]>,
+}
+
+impl<'a, E: Evaluator> Searcher<'a, E> {
+    fn new(evaluator: &'a E, tt: &'a TranspositionTable, limits: SearchLimits) -> Self {
+        let plies = MAX_DEPTH as usize + 1;
+        Searcher {
+            evaluator,
+            tt,
+            limits,
+            start: Instant::now(),
+            nodes: 0,
+            stopped: false,
+            pv: vec![vec![]; plies],
+            prev_pv: vec![],
+            root_best: None,
+            killers: vec![[None; 2]; plies],
+            history: vec![[0; SQUARE_COUNT]; SQUARE_COUNT + HAND_PIECE_TYPES.len()],
+        }
+    }
+
+    // 一定のノード数ごとに時間切れと止める指示を確認する
+    fn should_stop(&mut self) -> bool {
+        if self.nodes & (CHECK_INTERVAL - 1) == 0 {
+            if let Some(time) = self.limits.time {
+                self.stopped |= self.start.elapsed() >= time;
+            }
+            if let Some(stop) = &self.limits.stop {
+                self.stopped |= stop.load(Ordering::Relaxed);
+            }
+        }
+        self.stopped
+    }
+
+    // negamaxによるαβ探索
+    fn alpha_beta(
+        &mut self,
+        position: &Position,
+        depth: i32,
+        mut alpha: i32,
+        beta: i32,
+        ply: usize,
+    ) -> Result<i32> {
+        self.nodes += 1;
+        self.pv[ply].clear();
+        if self.should_stop() {
+            return Ok(0);
+        }
+        if depth <= 0 || ply >= MAX_DEPTH as usize {
+            return self.quiescence(position, alpha, beta, ply, self.limits.quiescence_checks);
+        }
+
+        // 同じ深さ以上で読んだ結果があればそれを使う (ルートは読み筋が必要なので使わない)
+        let entry = self.tt.probe(position.key());
+        if let Some(entry) = entry.filter(|e| ply > 0 && e.depth as i32 >= depth) {
+            let score = score_from_tt(entry.score, ply);
+            let cut = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+            };
+            if cut {
+                return Ok(score);
+            }
+        }
+
+        let mut moves = position.legal_moves();
+        // 指せる手がなければ詰み
+        if moves.is_empty() {
+            return Ok(-MATE + ply as i32);
+        }
+        self.order_moves(position, &mut moves, ply, entry.and_then(|e| e.best_move));
+
+        let original_alpha = alpha;
+        let mut best = -INFINITE;
+        let mut best_move = None;
+        for m in moves {
+            let next = position.move_piece(&m);
+            let score = -self.alpha_beta(&next, depth - 1, -beta, -alpha, ply + 1)?;
+            if self.stopped {
+                return Ok(0);
+            }
+            if score > best {
+                best = score;
+                best_move = Some(m);
+                if ply == 0 {
+                    self.root_best = Some((m, score));
+                }
+                let (head, tail) = self.pv.split_at_mut(ply + 1);
+                head[ply].clear();
+                head[ply].push(m);
+                head[ply].extend_from_slice(&tail[0]);
+            }
+            if score > alpha {
+                alpha = score;
+            }
+            if alpha >= beta {
+                if !is_capture(position, &m) {
+                    self.update_quiet_stats(&m, depth, ply);
+                }
+                break;
+            }
+        }
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best <= original_alpha {
+            Bound::Upper
+        } else {
+            Bound::Exact
+        };
+        self.tt.store(
+            position.key(),
+            TtEntry {
+                depth: depth as u8,
+                bound,
+                score: score_to_tt(best, ply),
+                best_move,
+            },
+        );
+        Ok(best)
+    }
+
+    // 駒を取る手・成る手だけを読んで、駒の取り合いが終わった局面を評価する
+    // 王手されている場合は全ての手を読む
+    fn quiescence(
+        &mut self,
+        position: &Position,
+        mut alpha: i32,
+        beta: i32,
+        ply: usize,
+        checks: bool,
+    ) -> Result<i32> {
+        self.nodes += 1;
+        if self.should_stop() {
+            return Ok(0);
+        }
+
+        let in_check = position.is_checked(position.turn());
+        let mut moves = position.legal_moves();
+        if moves.is_empty() {
+            return Ok(-MATE + ply as i32);
+        }
+        if ply >= MAX_DEPTH as usize {
+            return self.evaluator.evaluate(position);
+        }
+        let mut best = -INFINITE;
+        if !in_check {
+            // 何も指さない場合の評価値より悪くはならないとみなす
+            best = self.evaluator.evaluate(position)?;
+            if best >= beta {
+                return Ok(best);
+            }
+            alpha = alpha.max(best);
+            // 取り合いで損をする手は王手でなければ読まない
+            let check_moves = if checks {
+                position.check_moves()
+            } else {
+                vec![]
+            };
+            moves.retain(|m| {
+                let gives_check = check_moves.contains(m);
+                if is_capture(position, m) || matches!(m, Move::Normal { promote: true, .. }) {
+                    gives_check || position.see(m) >= 0
+                } else {
+                    gives_check
+                }
+            });
+        }
+        moves.sort_by_cached_key(|m| std::cmp::Reverse(mvv_lva(position, m)));
+
+        for m in moves {
+            let next = position.move_piece(&m);
+            let score = -self.quiescence(&next, -beta, -alpha, ply + 1, false)?;
+            if self.stopped {
+                return Ok(0);
+            }
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        Ok(best)
+    }
+
+    // 前回の読み筋の手、置換表の手、駒を取る手(価値の高い駒を安い駒で取る順)、キラー手、履歴の順に並べる
+    fn order_moves(
+        &self,
+        position: &Position,
+        moves: &mut [Move],
+        ply: usize,
+        tt_move: Option<Move>,
+    ) {
+        let pv_move = self.prev_pv.get(ply).copied();
+        moves.sort_by_cached_key(|m| {
+            let score = if Some(*m) == pv_move {
+                i32::MAX
+            } else if Some(*m) == tt_move {
+                i32::MAX - 1
+            } else if is_capture(position, m) && position.see(m) >= 0 {
+                1_000_000 + mvv_lva(position, m)
+            } else if self.killers[ply].contains(&Some(*m)) {
+                900_000
+            } else if is_capture(position, m) {
+                // 取り合いで損をする駒を取る手はキラー手の後に読む
+                800_000 + mvv_lva(position, m)
+            } else {
+                self.history[history_index(m)][m.to()]
+            };
+            std::cmp::Reverse(score)
+        });
+    }
+
+    fn update_quiet_stats(&mut self, m: &Move, depth: i32, ply: usize) {
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(*m) {
+            killers[1] = killers[0];
+            killers[0] = Some(*m);
+        }
+        let history = &mut self.history[history_index(m)][m.to()];
+        *history = (*history + depth * depth).min(800_000);
+    }
+}
+
+// 詰みの評価値はルートからの手数を含むので、置換表にはその局面からの手数で入れる
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE - MAX_DEPTH as i32 {
+        score + ply as i32
+    } else if score <= -MATE + MAX_DEPTH as i32 {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE - MAX_DEPTH as i32 {
+        score - ply as i32
+    } else if score <= -MATE + MAX_DEPTH as i32 {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+// 価値の高い駒を安い駒で取る手ほど大きくなる値 (成る手は成りで増える価値を加える)
+fn mvv_lva(position: &Position, m: &Move) -> i32 {
+    let victim = position
+        .piece_on(m.to())
+        .map_or(0, |p| piece_value(p.piece_type) * 16);
+    match *m {
+        Move::Normal { from, promote, .. } => {
+            let attacker = position.piece_on(from).unwrap();
+            let gain = if promote {
+                piece_value(attacker.revolute().piece_type) - piece_value(attacker.piece_type)
+            } else {
+                0
+            };
+            victim + gain - piece_value(attacker.piece_type) / 16
+        }
+        Move::Drop { piece_type, .. } => victim - piece_value(piece_type) / 16,
+    }
+}
+
+fn is_capture(position: &Position, m: &Move) -> bool {
+    position.piece_on(m.to()).is_some()
+}
+
+fn history_index(m: &Move) -> usize {
+    match *m {
+        Move::Normal { from, .. } => from,
+        Move::Drop { piece_type, .. } => SQUARE_COUNT + hand_index(piece_type).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bitboard::PIECE_TYPES,
+        board::create_legal_moves,
+        piece::PieceType,
+        sfen::{parse_sfen, INITIAL_SFEN},
+        usi::move_to_usi,
+    };
+
+    // 駒割りだけの評価関数
+    struct Material;
+
+    impl Evaluator for Material {
+        fn evaluate(&self, position: &Position) -> Result<i32> {
+            let score = |color: Color| {
+                let on_board = PIECE_TYPES
+                    .iter()
+                    .filter(|&&t| t != PieceType::King)
+                    .map(|&t| position.pieces(t, color).count() as i32 * piece_value(t))
+                    .sum::<i32>();
+                let in_hand = HAND_PIECE_TYPES
+                    .iter()
+                    .map(|&t| position.hand().count(t, color) as i32 * piece_value(t))
+                    .sum::<i32>();
+                on_board + in_hand
+            };
+            let turn = position.turn();
+            Ok(score(turn) - score(turn.opponent()))
+        }
+    }
+
+    fn search_sfen(sfen: &str, depth: u32) -> SearchResult {
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        let tt = TranspositionTable::new(1);
+        let limits = SearchLimits {
+            depth,
+            ..Default::default()
+        };
+        search(&boards, turn, &Material, &tt, limits, |_| {}).unwrap()
+    }
+
+    #[test]
+    fn mate_in_1() {
+        let result = search_sfen("4k4/9/4P4/9/9/9/9/9/4K4 b G 1", 2);
+        assert_eq!(move_to_usi(&result.best_move.unwrap()), "G*5b");
+        assert_eq!(result.score, MATE - 1);
+    }
+
+    #[test]
+    fn mate_in_3() {
+        // 3手目の後に指せる手がないことを確かめるには4手読む
+        let result = search_sfen("7nl/7k1/6ppp/9/9/9/9/9/K8 b RG 1", 4);
+        assert_eq!(result.score, MATE - 3);
+        assert_eq!(result.pv.len(), 3);
+    }
+
+    #[test]
+    fn captures_hanging_piece() {
+        // 5五の角は誰も守っていない
+        let result = search_sfen("4k4/9/9/9/4b4/4P4/9/9/4K4 b - 1", 3);
+        assert_eq!(move_to_usi(&result.best_move.unwrap()), "5f5e");
+        assert!(result.score >= piece_value(PieceType::Bishop));
+    }
+
+    #[test]
+    fn interrupted_depth_is_not_reported() {
+        // 駒を打つ手が多く、1手目を読み終わる前に時間切れになる
+        let sfen = "4k4/9/9/9/9/9/9/9/4K4 b RBGSNLP 1";
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        let tt = TranspositionTable::new(1);
+        let limits = SearchLimits {
+            time: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let mut iterations = 0;
+        let result = search(&boards, turn, &Material, &tt, limits, |_| iterations += 1).unwrap();
+        assert_eq!(iterations, 0);
+        assert_eq!(result.depth, 0);
+        assert!(create_legal_moves(&boards, turn).contains(&result.best_move.unwrap()));
+    }
+
+    #[test]
+    fn stop_flag_interrupts_search() {
+        let (boards, turn, _) = parse_sfen(INITIAL_SFEN).unwrap();
+        let tt = TranspositionTable::new(1);
+        let limits = SearchLimits {
+            stop: Some(Arc::new(AtomicBool::new(true))),
+            ..Default::default()
+        };
+        let mut iterations = 0;
+        let result = search(&boards, turn, &Material, &tt, limits, |_| iterations += 1).unwrap();
+        assert_eq!(iterations, 0);
+        assert_eq!(result.depth, 0);
+        assert!(result.best_move.is_some());
+    }
+
+    // 静的評価と静止探索の評価値
+    fn static_and_quiescence(sfen: &str) -> (i32, i32) {
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        let position = Position::from_boards(&boards, turn);
+        let tt = TranspositionTable::new(1);
+        let mut searcher = Searcher::new(&Material, &tt, SearchLimits::default());
+        let score = searcher
+            .quiescence(&position, -INFINITE, INFINITE, 0, false)
+            .unwrap();
+        (Material.evaluate(&position).unwrap(), score)
+    }
+
+    #[test]
+    fn quiescence_resolves_captures() {
+        // ただの角を取る
+        let (static_score, score) = static_and_quiescence("4k4/9/9/9/4b4/4P4/9/9/4K4 b - 1");
+        let (pawn, bishop) = (piece_value(PieceType::Pawn), piece_value(PieceType::Bishop));
+        assert_eq!(static_score, pawn - bishop);
+        assert_eq!(score, pawn + bishop);
+
+        // 5五の金を銀で取ると歩で取り返されるが、金と銀の差の分だけ得をする
+        let (static_score, score) = static_and_quiescence("4k4/9/9/4p4/4g4/4S4/9/9/4K4 b - 1");
+        assert_eq!(static_score, -135);
+        assert_eq!(
+            score,
+            static_score + piece_value(PieceType::Gold) * 2 - piece_value(PieceType::Silver) * 2
+        );
+
+        // 金で取ると歩で取り返されて損をするので取らない
+        let (static_score, score) = static_and_quiescence("4k4/9/9/4p4/4s4/4G4/9/9/4K4 b - 1");
+        assert_eq!(score, static_score);
+    }
+
+    #[test]
+    fn captures_are_ordered_by_mvv_lva() {
+        // 5五の飛車を歩と金で、9五の銀を歩で、1五の歩を香で取れる
+        let (boards, turn, _) = parse_sfen("4k4/9/9/9/s3r3p/P3PG3/9/9/4K3L b - 1").unwrap();
+        let position = Position::from_boards(&boards, turn);
+        let mut moves = position.legal_moves();
+        moves.sort_by_cached_key(|m| std::cmp::Reverse(mvv_lva(&position, m)));
+        let usi = moves
+            .iter()
+            .take(5)
+            .map(|m| move_to_usi(&m.to_legal_move(turn)))
+            .collect::<Vec<_>>();
+        assert_eq!(usi[..4], ["5f5e", "4f5e", "9f9e", "1i1e"]);
+        assert!(!is_capture(&position, &moves[4]));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{
+    board::{Boards, BOARD_SIZE, HAND_PIECE_TYPES},
+    piece::{Color, Piece, PieceType},
+};
+use anyhow::{anyhow, bail, Result};
+
+// 平手初期局面のSFEN
+pub const INITIAL_SFEN: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+// SFENの駒文字(大文字)から成る前の駒の種類を返す関数
+pub fn piece_type_from_char(c: char) -> Option<PieceType> {
+    match c {
+        'K' => Some(PieceType::King),
+        'R' => Some(PieceType::Rook),
+        'B' => Some(PieceType::Bishop),
+        'G' => Some(PieceType::Gold),
+        'S' => Some(PieceType::Silver),
+        'N' => Some(PieceType::Knight),
+        'L' => Some(PieceType::Lance),
+        'P' => Some(PieceType::Pawn),
+        _ => None,
+    }
+}
+
+// 駒の種類をSFENの駒文字(大文字)に変換する関数
+// 成り駒は成る前の駒の文字を返す
+pub fn piece_type_to_char(piece_type: PieceType) -> char {
+    match Piece::new(piece_type, Color::Black)
+        .revolute_back()
+        .piece_type
+    {
+        PieceType::King => 'K',
+        PieceType::Rook => 'R',
+        PieceType::Bishop => 'B',
+        PieceType::Gold => 'G',
+        PieceType::Silver => 'S',
+        PieceType::Knight => 'N',
+        PieceType::Lance => 'L',
+        _ => 'P',
+    }
+}
+
+// 駒をSFENの表記に変換する関数 (先手は大文字、後手は小文字、成り駒は+を付ける)
+pub fn piece_to_sfen(piece: &Piece) -> String {
+    let c = piece_type_to_char(piece.piece_type);
+    let c = match piece.color {
+        Color::Black => c,
+        Color::White => c.to_ascii_lowercase(),
+    };
+    if piece.revolute_back() != *piece {
+        format!("+{}", c)
+    } else {
+        c.to_string()
+    }
+}
+
+// SFEN文字列を盤面・手番・手数に変換する関数
+pub fn parse_sfen(sfen: &str) -> Result<(Boards, Color, u32)> {
+    let sfen = sfen.trim();
+    let sfen = sfen.strip_prefix("sfen ").unwrap_or(sfen);
+    let fields = sfen.split_whitespace().collect::<Vec<_>>();
+    if fields.len() < 3 {
+        bail!("invalid sfen: {}", sfen);
+    }
+    let mut boards = Boards::empty();
+
+    // 盤面 (一段目から九段目、各段は9筋から1筋の順)
+    let ranks = fields[0].split('/').collect::<Vec<_>>();
+    if ranks.len() != BOARD_SIZE {
+        bail!("invalid sfen board: {}", fields[0]);
+    }
+    for (rank, row) in ranks.iter().enumerate() {
+        let y = BOARD_SIZE - 1 - rank;
+        let mut file = 0;
+        let mut promoted = false;
+        for c in row.chars() {
+            if let Some(n) = c.to_digit(10) {
+                if promoted || n == 0 {
+                    bail!("invalid sfen board: {}", row);
+                }
+                file += n as usize;
+                continue;
+            }
+            if c == '+' {
+                promoted = true;
+                continue;
+            }
+            if file >= BOARD_SIZE {
+                bail!("too many squares in rank: {}", row);
+            }
+            let piece = piece_from_char(c, promoted)?;
+            boards.board[y][BOARD_SIZE - 1 - file] = Some(piece);
+            promoted = false;
+            file += 1;
+        }
+        if file != BOARD_SIZE || promoted {
+            bail!("invalid sfen rank: {}", row);
+        }
+    }
+
+    // 手番
+    let turn = match fields[1] {
+        "b" => Color::Black,
+        "w" => Color::White,
+        t => bail!("invalid sfen turn: {}", t),
+    };
+
+    // 持ち駒
+    if fields[2] != "-" {
+        let mut count = 0;
+        for c in fields[2].chars() {
+            if let Some(n) = c.to_digit(10) {
+                count = count * 10 + n;
+                continue;
+            }
+            let piece = piece_from_char(c, false)?;
+            if piece.piece_type == PieceType::King {
+                bail!("king in hand: {}", fields[2]);
+            }
+            for _ in 0..count.max(1) {
+                if !boards.hand.add(piece) {
+                    bail!("too many pieces in hand: {}", fields[2]);
+                }
+            }
+            count = 0;
+        }
+        if count != 0 {
+            bail!("invalid sfen hand: {}", fields[2]);
+        }
+    }
+
+    // 手数 (省略時は1)
+    let move_number = match fields.get(3) {
+        Some(n) => n
+            .parse::<u32>()
+            .map_err(|_| anyhow!("invalid sfen move number: {}", n))?,
+        None => 1,
+    };
+
+    Ok((boards, turn, move_number))
+}
+
+// 盤面・手番・手数をSFEN文字列に変換する関数
+pub fn to_sfen(boards: &Boards, turn: Color, move_number: u32) -> String {
+    let board = (0..BOARD_SIZE)
+        .rev()
+        .map(|y| {
+            let mut row = String::new();
+            let mut empty = 0;
+            for x in (0..BOARD_SIZE).rev() {
+                match boards.board[y][x] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            row.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        row.push_str(&piece_to_sfen(&piece));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                row.push_str(&empty.to_string());
+            }
+            row
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let turn = match turn {
+        Color::Black => "b",
+        Color::White => "w",
+    };
+
+    let mut hand = String::new();
+    for color in [Color::Black, Color::White] {
+        for piece_type in HAND_PIECE_TYPES {
+            let count = boards.hand.count(piece_type, color);
+            if count > 1 {
+                hand.push_str(&count.to_string());
+            }
+            if count > 0 {
+                hand.push_str(&piece_to_sfen(&Piece::new(piece_type, color)));
+            }
+        }
+    }
+    if hand.is_empty() {
+        hand.push('-');
+    }
+
+    format!("{} {} {} {}", board, turn, hand, move_number)
+}
+
+fn piece_from_char(c: char, promoted: bool) -> Result<Piece> {
+    let piece_type =
+        piece_type_from_char(c.to_ascii_uppercase()).ok_or(anyhow!("invalid sfen piece: {}", c))?;
+    let color = if c.is_ascii_uppercase() {
+        Color::Black
+    } else {
+        Color::White
+    };
+    let piece = Piece::new(piece_type, color);
+    if promoted {
+        if !piece.can_revolte() {
+            bail!("piece can not be promoted: +{}", c);
+        }
+        Ok(piece.revolute())
+    } else {
+        Ok(piece)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::create_initial_board;
+
+    fn round_trip(sfen: &str) -> (Boards, Color, u32) {
+        let (boards, turn, move_number) = parse_sfen(sfen).unwrap();
+        assert_eq!(to_sfen(&boards, turn, move_number), sfen);
+        (boards, turn, move_number)
+    }
+
+    #[test]
+    fn initial_position() {
+        let (boards, turn, move_number) = round_trip(INITIAL_SFEN);
+        assert_eq!(boards, create_initial_board());
+        assert_eq!((turn, move_number), (Color::Black, 1));
+        // y=0が九段目(先手側)、x=0が1筋
+        assert_eq!(
+            boards.board[0][4],
+            Some(Piece::new(PieceType::King, Color::Black))
+        );
+        assert_eq!(
+            boards.board[1][1],
+            Some(Piece::new(PieceType::Rook, Color::Black))
+        );
+        assert_eq!(
+            boards.board[8][0],
+            Some(Piece::new(PieceType::Lance, Color::White))
+        );
+        assert_eq!(
+            parse_sfen(&format!("sfen {}", INITIAL_SFEN)).unwrap().0,
+            boards
+        );
+    }
+
+    #[test]
+    fn hands_and_promoted_pieces() {
+        let (boards, _, _) = round_trip("4k4/9/4+P4/9/9/9/9/1+r7/4K4 b R2B4G4S4N4L10Pr7p 37");
+        assert_eq!(boards.hand.count(PieceType::Pawn, Color::Black), 10);
+        assert_eq!(boards.hand.count(PieceType::Pawn, Color::White), 7);
+        assert_eq!(boards.hand.count(PieceType::Rook, Color::White), 1);
+        assert_eq!(
+            boards.board[6][4],
+            Some(Piece::new(PieceType::PromotedPawn, Color::Black))
+        );
+        assert_eq!(
+            boards.board[1][7],
+            Some(Piece::new(PieceType::Dragon, Color::White))
+        );
+        // 持ち駒は飛車から歩の順、先手から書く
+        let (boards, turn, move_number) = parse_sfen("4k4/9/9/9/9/9/9/9/4K4 b p10PG 1").unwrap();
+        assert_eq!(
+            to_sfen(&boards, turn, move_number),
+            "4k4/9/9/9/9/9/9/9/4K4 b G10Pp 1"
+        );
+    }
+
+    #[test]
+    fn white_to_move() {
+        let sfen = "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2";
+        let (boards, turn, move_number) = round_trip(sfen);
+        assert_eq!((turn, move_number), (Color::White, 2));
+        assert_eq!(
+            boards.board[3][6],
+            Some(Piece::new(PieceType::Pawn, Color::Black))
+        );
+        // 手数は省略できる
+        let sfen = "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w -";
+        assert_eq!(parse_sfen(sfen).unwrap(), (boards, Color::White, 1));
+    }
+
+    #[test]
+    fn malformed_sfen_is_rejected() {
+        let sfens = [
+            "",
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b",
+            // 段の数・升の数
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNLL b - 1",
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/8/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/09/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            // 駒の文字・成れない駒・+の後に駒がない
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/4X4/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            "lnsg+kgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/8+/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            // 手番
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL x - 1",
+            // 持ち駒 (玉・枚数だけ・多すぎる)
+            "4k4/9/9/9/9/9/9/9/4K4 b K 1",
+            "4k4/9/9/9/9/9/9/9/4K4 b P2 1",
+            "4k4/9/9/9/9/9/9/9/4K4 b 19P 1",
+            "4k4/9/9/9/9/9/9/9/4K4 b 3R 1",
+            // 手数
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - one",
+        ];
+        for sfen in sfens {
+            assert!(parse_sfen(sfen).is_err(), "{}", sfen);
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use crate::{
+    bitboard::{Move, Position},
+    board::{Boards, LegalMove},
+    piece::Color,
+};
+use std::collections::{HashMap, HashSet};
+
+// 証明数・反証数の無限大
+const INFINITE: u32 = u32::MAX / 2;
+// 読む手数の上限
+const MAX_PLY: usize = 255;
+
+// 詰将棋を解いた結果
+#[derive(Debug, Clone, PartialEq)]
+pub enum TsumeResult {
+    // 詰み (攻め方の手から順に玉が詰むまでの手順、手数は手順の長さ)
+    Mate(Vec<LegalMove>),
+    // 詰まないことを証明した
+    NoMate,
+    // 探索するノード数の上限までに分からなかった
+    // (千日手や手数の上限で打ち切った読みでしか不詰みにならなかった場合も含む)
+    Unknown,
+}
+
+// 手番の側を攻め方として、王手の連続で相手の玉が詰むかをdf-pnで解く関数
+// 攻め方の玉はなくてもよい (詰将棋の局面は検証しない)
+pub fn solve(boards: &Boards, turn: Color, max_nodes: u64) -> TsumeResult {
+    let root = Position::from_boards(boards, turn);
+    let mut solver = Solver {
+        attacker: turn,
+        table: HashMap::new(),
+        path: HashSet::new(),
+        nodes: 0,
+        max_nodes,
+    };
+    let value = solver.mid(&root, INFINITE - 1, INFINITE - 1, 0);
+    if value.phi == 0 {
+        // 手順を取り出せない場合 (手数の上限など) は分からなかったことにする
+        let Some(moves) = solver.mate_moves(&root) else {
+            return TsumeResult::Unknown;
+        };
+        TsumeResult::Mate(
+            moves
+                .iter()
+                .scan(turn, |turn, m| {
+                    let legal_move = m.to_legal_move(*turn);
+                    *turn = turn.opponent();
+                    Some(legal_move)
+                })
+                .collect(),
+        )
+    } else if value.delta == 0 && !value.path_dependent {
+        TsumeResult::NoMate
+    } else {
+        // 千日手や手数の上限による不詰みは読んだ手順によるので証明にならない
+        TsumeResult::Unknown
+    }
+}
+
+// 局面の証明数・反証数
+// 手番の側から見た値で、攻め方の局面ではφが証明数、玉方の局面ではφが反証数
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Value {
+    phi: u32,
+    delta: u32,
+    // 読んでいる手順での千日手や手数の上限を使って決まった値かどうか
+    // 別の手順から同じ局面に来たときには成り立たないことがある (GHI問題)
+    path_dependent: bool,
+}
+
+impl Value {
+    const fn new(phi: u32, delta: u32) -> Value {
+        Value {
+            phi,
+            delta,
+            path_dependent: false,
+        }
+    }
+}
+
+struct Solver {
+    attacker: Color,
+    // 局面ごとの(φ, δ)
+    table: HashMap<u64, Value>,
+    // 今読んでいる手順に出てきた局面 (同一局面は連続王手の千日手なので不詰みとする)
+    path: HashSet<u64>,
+    nodes: u64,
+    max_nodes: u64,
+}
+
+impl Solver {
+    fn is_or_node(&self, position: &Position) -> bool {
+        position.turn() == self.attacker
+    }
+
+    fn moves(&self, position: &Position) -> Vec<Move> {
+        // 攻め方は王手になる手、玉方は全ての合法手を読む
+        if self.is_or_node(position) {
+            position.check_moves()
+        } else {
+            position.legal_moves()
+        }
+    }
+
+    // 子局面の(φ, δ)
+    fn lookup(&self, position: &Position, ply: usize) -> Value {
+        if self.path.contains(&position.key()) || ply >= MAX_PLY {
+            return self.not_mate(position);
+        }
+        self.table
+            .get(&position.key())
+            .copied()
+            .unwrap_or(Value::new(1, 1))
+    }
+
+    // 千日手や手数の上限で不詰みとするときの(φ, δ)
+    fn not_mate(&self, position: &Position) -> Value {
+        let (phi, delta) = if self.is_or_node(position) {
+            (INFINITE, 0)
+        } else {
+            (0, INFINITE)
+        };
+        Value {
+            phi,
+            delta,
+            path_dependent: true,
+        }
+    }
+
+    // 閾値を超えるまで局面を展開する (多重反復深化)
+    fn mid(&mut self, position: &Position, th_phi: u32, th_delta: u32, ply: usize) -> Value {
+        self.nodes += 1;
+        let moves = self.moves(position);
+        if moves.is_empty() {
+            // 攻め方は王手がなければ不詰み、玉方は逃げる手がなければ詰みで、どちらも手番の側の負け
+            let result = Value::new(INFINITE, 0);
+            self.table.insert(position.key(), result);
+            return result;
+        }
+        let children = moves
+            .iter()
+            .map(|m| position.move_piece(m))
+            .collect::<Vec<_>>();
+
+        self.path.insert(position.key());
+        let result = loop {
+            let values = children
+                .iter()
+                .map(|child| self.lookup(child, ply + 1))
+                .collect::<Vec<_>>();
+            // 子の(φ, δ)から自分の(φ, δ)を求める
+            let phi = values.iter().map(|v| v.delta).min().unwrap();
+            let delta = values
+                .iter()
+                .fold(0, |sum: u32, v| sum.saturating_add(v.phi))
+                .min(INFINITE);
+            if phi >= th_phi || delta >= th_delta || self.nodes >= self.max_nodes {
+                // 負けは全ての子の勝ちに、勝ちは勝てる子のどれかによる
+                let path_dependent = if delta == 0 {
+                    values.iter().any(|v| v.path_dependent)
+                } else if phi == 0 {
+                    values
+                        .iter()
+                        .filter(|v| v.delta == 0)
+                        .all(|v| v.path_dependent)
+                } else {
+                    false
+                };
+                break Value {
+                    phi,
+                    delta,
+                    path_dependent,
+                };
+            }
+            // δが最小の子を閾値を決めて展開する (閾値には2番目に小さいδを使う)
+            let mut best = 0;
+            let mut second = INFINITE;
+            for (i, v) in values.iter().enumerate().skip(1) {
+                if v.delta < values[best].delta {
+                    second = values[best].delta;
+                    best = i;
+                } else {
+                    second = second.min(v.delta);
+                }
+            }
+            let child_th_phi = th_delta
+                .saturating_sub(delta)
+                .saturating_add(values[best].phi)
+                .min(INFINITE - 1);
+            let child_th_delta = th_phi.min(second.saturating_add(1)).min(INFINITE - 1);
+            self.mid(&children[best], child_th_phi, child_th_delta, ply + 1);
+        };
+        self.path.remove(&position.key());
+        self.table.insert(position.key(), result);
+        result
+    }
+
+    // 証明された局面から詰み手順を取り出す
+    // 攻め方は一番短く詰む手、玉方は一番長く逃れる手を選ぶ
+    // 手数の上限や千日手で手順を取り出せない場合はNoneを返す
+    fn mate_moves(&self, root: &Position) -> Option<Vec<Move>> {
+        let mut lengths = HashMap::new();
+        let mut path = HashSet::new();
+        self.mate_length(root, &mut lengths, &mut path)?;
+        let mut moves = vec![];
+        let mut position = *root;
+        while let Some(&(_, Some(m))) = lengths.get(&position.key()) {
+            moves.push(m);
+            position = position.move_piece(&m);
+        }
+        Some(moves)
+    }
+
+    // 局面から詰むまでの手数と最善手 (詰まないか分からない場合はNone)
+    fn mate_length(
+        &self,
+        position: &Position,
+        lengths: &mut HashMap<u64, (usize, Option<Move>)>,
+        path: &mut HashSet<u64>,
+    ) -> Option<usize> {
+        let key = position.key();
+        if let Some(&(length, _)) = lengths.get(&key) {
+            return Some(length);
+        }
+        let proven = |value: &Value| {
+            if self.is_or_node(position) {
+                value.phi == 0
+            } else {
+                value.delta == 0
+            }
+        };
+        if path.len() >= MAX_PLY || path.contains(&key) || !self.table.get(&key).is_some_and(proven)
+        {
+            return None;
+        }
+        path.insert(key);
+        let candidates = self.moves(position).into_iter().map(|m| {
+            let length = self.mate_length(&position.move_piece(&m), lengths, path);
+            (m, length)
+        });
+        let best = if self.is_or_node(position) {
+            candidates
+                .filter_map(|(m, length)| Some((length?, m)))
+                .min_by_key(|&(length, _)| length)
+        } else {
+            // 玉方は全ての手で詰んでいなければならない
+            let mut best: Option<(usize, Move)> = None;
+            let mut all_mate = true;
+            for (m, length) in candidates {
+                match length {
+                    Some(length) if best.is_none_or(|(l, _)| length > l) => {
+                        best = Some((length, m))
+                    }
+                    Some(_) => {}
+                    None => all_mate = false,
+                }
+            }
+            best.filter(|_| all_mate)
+        };
+        path.remove(&key);
+        let length = match best {
+            Some((length, m)) => {
+                lengths.insert(key, (length + 1, Some(m)));
+                length + 1
+            }
+            // 逃げる手がない局面は詰み
+            None if !self.is_or_node(position) && self.moves(position).is_empty() => {
+                lengths.insert(key, (0, None));
+                0
+            }
+            None => return None,
+        };
+        Some(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sfen::parse_sfen, usi::move_to_usi};
+
+    fn solve_sfen(sfen: &str) -> TsumeResult {
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        solve(&boards, turn, 1_000_000)
+    }
+
+    // 詰み手順が王手の連続で、最後に玉方が指せなくなることを確かめて手順をUSIで返す
+    fn mate_line(sfen: &str) -> Vec<String> {
+        let TsumeResult::Mate(moves) = solve_sfen(sfen) else {
+            panic!("no mate found: {}", sfen);
+        };
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        let mut position = Position::from_boards(&boards, turn);
+        for (i, m) in moves.iter().enumerate() {
+            let m = Move::from_legal_move(m);
+            assert!(position.legal_moves().contains(&m));
+            position = position.move_piece(&m);
+            if i % 2 == 0 {
+                assert!(position.is_checked(turn.opponent()));
+            }
+        }
+        assert!(position.legal_moves().is_empty());
+        moves.iter().map(move_to_usi).collect()
+    }
+
+    #[test]
+    fn mate_in_1() {
+        assert_eq!(mate_line("4k4/9/4P4/9/9/9/9/9/9 b G 1"), ["G*5b"]);
+    }
+
+    #[test]
+    fn mate_in_3() {
+        assert_eq!(mate_line("7nl/7k1/6ppp/9/9/9/9/9/K8 b RG 1").len(), 3);
+    }
+
+    #[test]
+    fn longer_mates() {
+        assert_eq!(mate_line("7nl/7k1/6ppp/9/9/9/9/9/9 b RB 1").len(), 5);
+        assert_eq!(mate_line("7nl/7k1/6ppp/9/9/9/9/9/9 b RN 1").len(), 7);
+    }
+
+    #[test]
+    fn no_mate() {
+        // 桂を打って王手できるが詰まない
+        assert_eq!(solve_sfen("4k4/9/9/9/9/9/9/9/9 b N 1"), TsumeResult::NoMate);
+        // 王手がない
+        assert_eq!(
+            solve_sfen("4k4/9/4P4/9/9/9/9/9/9 b - 1"),
+            TsumeResult::NoMate
+        );
+    }
+
+    #[test]
+    fn uchifuzume_is_not_mate() {
+        // 歩を打てば詰むが打ち歩詰めなので詰まない
+        assert_eq!(
+            solve_sfen("7lk/7p1/8G/9/9/9/9/9/K8 b P 1"),
+            TsumeResult::NoMate
+        );
+        // 香なら打って詰む
+        assert_eq!(mate_line("7lk/7p1/8G/9/9/9/9/9/K8 b L 1"), ["L*1b"]);
+    }
+
+    #[test]
+    fn repetition_is_not_a_disproof() {
+        // 飛車で王手を続けられるが、千日手で打ち切った読みは不詰みの証明にしない
+        assert_eq!(
+            solve_sfen("7gk/9/9/9/9/9/9/9/9 b R 1"),
+            TsumeResult::Unknown
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{
+    bitboard::{Move, SQUARE_COUNT},
+    board::{hand_index, HAND_PIECE_TYPES},
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// 置換表の1エントリ (ハッシュ値とデータの2語) の大きさ
+const ENTRY_SIZE: usize = std::mem::size_of::<[AtomicU64; 2]>();
+
+// 評価値の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    // 正確な値
+    Exact,
+    // 真の値はこれ以上 (βカットした)
+    Lower,
+    // 真の値はこれ以下 (αを超えなかった)
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TtEntry {
+    pub depth: u8,
+    pub bound: Bound,
+    pub score: i32,
+    pub best_move: Option<Move>,
+}
+
+// 局面のハッシュ値をキーにした探索結果の表
+// 各エントリは(ハッシュ値 ^ データ, データ)の2語で持ち、読むときに組が崩れていないか確かめる
+// ロックを使わないので複数のスレッドから同時に読み書きできる (崩れたエントリは無かったことにする)
+pub struct TranspositionTable {
+    entries: Vec<[AtomicU64; 2]>,
+}
+
+impl TranspositionTable {
+    // 大きさをMB単位で指定する (エントリ数は2の累乗に切り下げる)
+    pub fn new(mb: usize) -> Self {
+        let count = 1 << (mb.max(1) * 1024 * 1024 / ENTRY_SIZE).ilog2();
+        TranspositionTable {
+            entries: (0..count)
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    fn entry(&self, key: u64) -> &[AtomicU64; 2] {
+        &self.entries[key as usize & (self.entries.len() - 1)]
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TtEntry> {
+        let [check, data] = self.entry(key);
+        let data = data.load(Ordering::Relaxed);
+        if data == 0 || check.load(Ordering::Relaxed) ^ data != key {
+            return None;
+        }
+        Some(unpack(data))
+    }
+
+    // 同じ局面なら浅い探索で深い結果を上書きしない (正確な値は常に書く)
+    // 別の局面なら常に置き換える
+    pub fn store(&self, key: u64, entry: TtEntry) {
+        if let Some(old) = self.probe(key) {
+            if entry.bound != Bound::Exact && entry.depth < old.depth {
+                return;
+            }
+        }
+        let [check, data] = self.entry(key);
+        let packed = pack(&entry);
+        data.store(packed, Ordering::Relaxed);
+        check.store(key ^ packed, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for [check, data] in self.entries.iter() {
+            check.store(0, Ordering::Relaxed);
+            data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    // 使われているエントリの割合 (1000分率、USIのhashfull用)
+    pub fn hashfull(&self) -> usize {
+        let sample = self.entries.len().min(1000);
+        let used = self.entries[..sample]
+            .iter()
+            .filter(|[_, data]| data.load(Ordering::Relaxed) != 0)
+            .count();
+        used * 1000 / sample
+    }
+}
+
+// データの並び: 評価値16bit | 指し手16bit | 深さ8bit | 種類2bit | 使用中1bit
+fn pack(entry: &TtEntry) -> u64 {
+    let bound = match entry.bound {
+        Bound::Exact => 0,
+        Bound::Lower => 1,
+        Bound::Upper => 2,
+    };
+    (entry.score as i16 as u16 as u64)
+        | (encode_move(entry.best_move) as u64) << 16
+        | (entry.depth as u64) << 32
+        | bound << 40
+        | 1 << 42
+}
+
+fn unpack(data: u64) -> TtEntry {
+    TtEntry {
+        depth: (data >> 32) as u8,
+        bound: match (data >> 40) & 3 {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        },
+        score: data as u16 as i16 as i32,
+        best_move: decode_move((data >> 16) as u16),
+    }
+}
+
+// 指し手を16bitにする: 移動先7bit | 移動元7bit(打つ手は81+持ち駒の種類) | 成り1bit | 有無1bit
+fn encode_move(m: Option<Move>) -> u16 {
+    let Some(m) = m else {
+        return 0;
+    };
+    let (from, promote) = match m {
+        Move::Normal { from, promote, .. } => (from, promote),
+        Move::Drop { piece_type, .. } => (SQUARE_COUNT + hand_index(piece_type).unwrap(), false),
+    };
+    m.to() as u16 | (from as u16) << 7 | (promote as u16) << 14 | 1 << 15
+}
+
+fn decode_move(data: u16) -> Option<Move> {
+    if data & 1 << 15 == 0 {
+        return None;
+    }
+    let to = (data & 0x7f) as usize;
+    let from = ((data >> 7) & 0x7f) as usize;
+    if from >= SQUARE_COUNT {
+        return Some(Move::Drop {
+            piece_type: HAND_PIECE_TYPES[from - SQUARE_COUNT],
+            to,
+        });
+    }
+    Some(Move::Normal {
+        from,
+        to,
+        promote: data & 1 << 14 != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_is_rounded_down_to_power_of_two() {
+        // 1エントリ16バイトなので256MBはちょうど2^24エントリ
+        assert_eq!(TranspositionTable::new(256).entries.len(), 1 << 24);
+        assert_eq!(TranspositionTable::new(3).entries.len(), 1 << 17);
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let entries = [
+            TtEntry {
+                depth: 12,
+                bound: Bound::Exact,
+                score: -1234,
+                best_move: Some(Move::Normal {
+                    from: 10,
+                    to: 80,
+                    promote: true,
+                }),
+            },
+            TtEntry {
+                depth: 255,
+                bound: Bound::Lower,
+                score: 29_995,
+                best_move: Some(Move::Drop {
+                    piece_type: HAND_PIECE_TYPES[HAND_PIECE_TYPES.len() - 1],
+                    to: 0,
+                }),
+            },
+            TtEntry {
+                depth: 0,
+                bound: Bound::Upper,
+                score: -29_990,
+                best_move: None,
+            },
+        ];
+        for entry in entries {
+            assert_eq!(unpack(pack(&entry)), entry);
+        }
+    }
+
+    #[test]
+    fn store_and_probe() {
+        let tt = TranspositionTable::new(1);
+        let entry = TtEntry {
+            depth: 3,
+            bound: Bound::Exact,
+            score: -30_000,
+            best_move: None,
+        };
+        tt.store(0x1234_5678_9abc_def0, entry);
+        assert_eq!(tt.probe(0x1234_5678_9abc_def0), Some(entry));
+        assert_eq!(tt.probe(0x1234_5678_9abc_def1), None);
+    }
+}
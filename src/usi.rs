@@ -0,0 +1,168 @@
+use crate::{
+    board::{create_move_range, find_drop_move, Boards, LegalMove, Position, BOARD_SIZE},
+    piece::Color,
+    sfen::{piece_type_from_char, piece_type_to_char},
+};
+use anyhow::{anyhow, bail, Result};
+
+// 盤上の座標をUSIのマス表記(例: 7g)に変換する関数
+// x=0が1筋、y=0が九段目(先手側)に対応する
+pub fn square_to_usi(position: Position) -> String {
+    let file = position.x + 1;
+    let rank = (b'a' + (BOARD_SIZE as i32 - 1 - position.y) as u8) as char;
+    format!("{}{}", file, rank)
+}
+
+// USIのマス表記を盤上の座標に変換する関数
+pub fn square_from_usi(square: &str) -> Result<Position> {
+    let mut chars = square.chars();
+    let (Some(file), Some(rank), None) = (chars.next(), chars.next(), chars.next()) else {
+        bail!("invalid usi square: {}", square);
+    };
+    let file = file
+        .to_digit(10)
+        .filter(|f| (1..=BOARD_SIZE as u32).contains(f))
+        .ok_or(anyhow!("invalid usi file: {}", square))?;
+    if !('a'..='i').contains(&rank) {
+        bail!("invalid usi rank: {}", square);
+    }
+    let rank = rank as i32 - 'a' as i32;
+    Ok(Position::new(
+        file as i32 - 1,
+        BOARD_SIZE as i32 - 1 - rank,
+        0,
+    ))
+}
+
+// 指し手をUSIの表記(例: 7g7f, 8h2b+, P*5e)に変換する関数
+pub fn move_to_usi(legal_move: &LegalMove) -> String {
+    let to = square_to_usi(legal_move.to);
+    if let Some(piece) = legal_move.drop_piece() {
+        format!("{}*{}", piece_type_to_char(piece.piece_type), to)
+    } else {
+        let from = square_to_usi(legal_move.from);
+        let revolute = if legal_move.revolute { "+" } else { "" };
+        format!("{}{}{}", from, to, revolute)
+    }
+}
+
+// USIの表記を指し手に変換する関数
+// 現在の局面で指せない手(駒の動きとして不正な手)の場合はエラーを返す
+pub fn move_from_usi(boards: &Boards, turn: Color, usi: &str) -> Result<LegalMove> {
+    let usi = usi.trim();
+    if let Some((piece, to)) = usi.split_once('*') {
+        let mut chars = piece.chars();
+        let piece_type = match (chars.next(), chars.next()) {
+            (Some(c), None) => piece_type_from_char(c),
+            _ => None,
+        }
+        .ok_or(anyhow!("invalid usi drop piece: {}", usi))?;
+        let to = square_from_usi(to)?;
+        return find_drop_move(boards, turn, piece_type, to)
+            .ok_or(anyhow!("illegal usi move: {}", usi));
+    }
+
+    let (body, revolute) = match usi.strip_suffix('+') {
+        Some(body) => (body, true),
+        None => (usi, false),
+    };
+    if body.len() != 4 || !body.is_ascii() {
+        bail!("invalid usi move: {}", usi);
+    }
+    let legal_move = LegalMove {
+        from: square_from_usi(&body[0..2])?,
+        to: square_from_usi(&body[2..4])?,
+        revolute,
+    };
+    let piece = boards.board[legal_move.from.y as usize][legal_move.from.x as usize];
+    if !matches!(piece, Some(p) if p.color == turn) {
+        bail!("no piece to move: {}", usi);
+    }
+    if !create_move_range(boards, turn).contains(&legal_move) {
+        bail!("illegal usi move: {}", usi);
+    }
+    Ok(legal_move)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        piece::{Piece, PieceType},
+        sfen::{parse_sfen, INITIAL_SFEN},
+    };
+
+    fn board(sfen: &str) -> (Boards, Color) {
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        (boards, turn)
+    }
+
+    #[test]
+    fn squares() {
+        // x=0が1筋、y=0が九段目
+        for (usi, x, y) in [("1a", 0, 8), ("9i", 8, 0), ("7g", 6, 2), ("5e", 4, 4)] {
+            let position = Position::new(x, y, 0);
+            assert_eq!(square_to_usi(position), usi);
+            assert_eq!(square_from_usi(usi).unwrap(), position);
+        }
+        for usi in ["0a", "1j", "a1", "7", "7g7"] {
+            assert!(square_from_usi(usi).is_err(), "{}", usi);
+        }
+    }
+
+    #[test]
+    fn normal_move() {
+        let (boards, turn) = board(INITIAL_SFEN);
+        let m = move_from_usi(&boards, turn, "7g7f").unwrap();
+        assert_eq!(
+            m,
+            LegalMove {
+                from: Position::new(6, 2, 0),
+                to: Position::new(6, 3, 0),
+                revolute: false,
+            }
+        );
+        assert_eq!(move_to_usi(&m), "7g7f");
+    }
+
+    #[test]
+    fn promotion() {
+        let (boards, turn) =
+            board("lnsgkgsnl/1r5b1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 3");
+        let m = move_from_usi(&boards, turn, "8h2b+").unwrap();
+        assert_eq!(
+            m,
+            LegalMove {
+                from: Position::new(7, 1, 0),
+                to: Position::new(1, 7, 0),
+                revolute: true,
+            }
+        );
+        assert_eq!(move_to_usi(&m), "8h2b+");
+        let m = move_from_usi(&boards, turn, "8h2b").unwrap();
+        assert!(!m.revolute);
+        assert_eq!(move_to_usi(&m), "8h2b");
+    }
+
+    #[test]
+    fn drop() {
+        // 後手が打つ手も駒の文字は大文字
+        for sfen in ["4k4/9/9/9/9/9/9/9/4K4 b P 1", "4k4/9/9/9/9/9/9/9/4K4 w p 1"] {
+            let (boards, turn) = board(sfen);
+            let m = move_from_usi(&boards, turn, "P*5e").unwrap();
+            assert_eq!(m.to, Position::new(4, 4, 0));
+            assert_eq!(m.drop_piece(), Some(Piece::new(PieceType::Pawn, turn)));
+            assert_eq!(move_to_usi(&m), "P*5e");
+        }
+    }
+
+    #[test]
+    fn invalid_moves() {
+        let (boards, turn) = board(INITIAL_SFEN);
+        for usi in [
+            "7g7e", "5e5d", "3c3d", "7g7f+", "P*5e", "K*5e", "7g", "7g7f++", "Pawn*5e",
+        ] {
+            assert!(move_from_usi(&boards, turn, usi).is_err(), "{}", usi);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{
+    bitboard::{is_dead_square, Position},
+    board::{Boards, BOARD_SIZE, HAND_MAX_COUNTS, HAND_PIECE_TYPES},
+    piece::{Color, Piece, PieceType},
+};
+use std::fmt;
+
+// 局面の問題点
+// 升はx(筋-1), y(9-段)のBoardsの座標で持つ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PositionError {
+    // 玉がない
+    MissingKing(Color),
+    // 玉が2枚以上ある
+    DuplicateKing(Color),
+    // 同じ筋に歩が2枚以上ある
+    Nifu { color: Color, x: usize },
+    // 以後動けなくなる升にある歩・香・桂
+    DeadPiece { piece: Piece, x: usize, y: usize },
+    // 駒の数が1組の駒より多い (成り駒・持ち駒を含む)
+    TooManyPieces(PieceType),
+    // 手番でない側の玉が王手されている
+    OpponentInCheck(Color),
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PositionError::MissingKing(color) => write!(f, "{:?} king is missing", color),
+            PositionError::DuplicateKing(color) => write!(f, "{:?} has more than one king", color),
+            PositionError::Nifu { color, x } => {
+                write!(f, "{:?} has two pawns on file {}", color, x + 1)
+            }
+            PositionError::DeadPiece { piece, x, y } => write!(
+                f,
+                "{:?} {:?} can never move from {}{}",
+                piece.color,
+                piece.piece_type,
+                x + 1,
+                BOARD_SIZE - y
+            ),
+            PositionError::TooManyPieces(piece_type) => {
+                write!(f, "too many pieces of {:?}", piece_type)
+            }
+            PositionError::OpponentInCheck(color) => {
+                write!(f, "{:?} king is in check on the opponent's turn", color)
+            }
+        }
+    }
+}
+
+// 局面の問題点を全て返す関数 (問題がなければ空)
+// turnは手番の側
+pub fn validate(boards: &Boards, turn: Color) -> Vec<PositionError> {
+    let mut errors = vec![];
+    let pieces = boards.board.iter().enumerate().flat_map(|(y, row)| {
+        row.iter()
+            .enumerate()
+            .filter_map(move |(x, piece)| piece.map(|p| (x, y, p)))
+    });
+
+    for color in [Color::Black, Color::White] {
+        let kings = pieces
+            .clone()
+            .filter(|(_, _, p)| p.color == color && p.piece_type == PieceType::King)
+            .count();
+        match kings {
+            0 => errors.push(PositionError::MissingKing(color)),
+            1 => {}
+            _ => errors.push(PositionError::DuplicateKing(color)),
+        }
+    }
+
+    for color in [Color::Black, Color::White] {
+        for x in 0..BOARD_SIZE {
+            let pawns = pieces
+                .clone()
+                .filter(|&(px, _, p)| px == x && p == Piece::new(PieceType::Pawn, color))
+                .count();
+            if pawns > 1 {
+                errors.push(PositionError::Nifu { color, x });
+            }
+        }
+    }
+
+    for (x, y, piece) in pieces.clone() {
+        if is_dead_square(piece.piece_type, piece.color, y as i32) {
+            errors.push(PositionError::DeadPiece { piece, x, y });
+        }
+    }
+
+    for (i, piece_type) in HAND_PIECE_TYPES.iter().enumerate() {
+        let on_board = pieces
+            .clone()
+            .filter(|(_, _, p)| p.revolute_back().piece_type == *piece_type)
+            .count();
+        let in_hand = [Color::Black, Color::White]
+            .iter()
+            .map(|&color| boards.hand.count(*piece_type, color) as usize)
+            .sum::<usize>();
+        // 持ち駒の最大枚数はその駒の総数と同じ
+        if on_board + in_hand > HAND_MAX_COUNTS[i] as usize {
+            errors.push(PositionError::TooManyPieces(*piece_type));
+        }
+    }
+
+    // 手番でない側の玉に利きがあれば、直前の手が自殺手だったことになる
+    let opponent = turn.opponent();
+    if Position::from_boards(boards, turn).is_checked(opponent) {
+        errors.push(PositionError::OpponentInCheck(opponent));
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfen::{parse_sfen, INITIAL_SFEN};
+
+    fn errors(sfen: &str) -> Vec<PositionError> {
+        let (boards, turn, _) = parse_sfen(sfen).unwrap();
+        validate(&boards, turn)
+    }
+
+    #[test]
+    fn initial_position_is_valid() {
+        assert_eq!(errors(INITIAL_SFEN), vec![]);
+    }
+
+    #[test]
+    fn missing_king() {
+        assert_eq!(
+            errors("9/9/9/9/9/9/9/9/4K4 b - 1"),
+            vec![PositionError::MissingKing(Color::White)]
+        );
+    }
+
+    #[test]
+    fn duplicate_king() {
+        assert_eq!(
+            errors("4k4/9/9/9/9/9/9/9/K3K4 b - 1"),
+            vec![PositionError::DuplicateKing(Color::Black)]
+        );
+    }
+
+    #[test]
+    fn nifu() {
+        assert_eq!(
+            errors("4k4/9/9/9/9/2P6/9/2P6/4K4 b - 1"),
+            vec![PositionError::Nifu {
+                color: Color::Black,
+                x: 6
+            }]
+        );
+    }
+
+    #[test]
+    fn dead_pieces() {
+        // 1一の歩, 2二の桂, 9九の後手の香
+        assert_eq!(
+            errors("4k3P/7N1/9/9/9/9/9/9/l3K4 b - 1"),
+            vec![
+                PositionError::DeadPiece {
+                    piece: Piece::new(PieceType::Lance, Color::White),
+                    x: 8,
+                    y: 0
+                },
+                PositionError::DeadPiece {
+                    piece: Piece::new(PieceType::Knight, Color::Black),
+                    x: 1,
+                    y: 7
+                },
+                PositionError::DeadPiece {
+                    piece: Piece::new(PieceType::Pawn, Color::Black),
+                    x: 0,
+                    y: 8
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn too_many_pieces() {
+        // 盤上の飛車2枚と龍1枚
+        assert_eq!(
+            errors("4k4/9/9/9/9/9/9/+R8/R3K3R b - 1"),
+            vec![PositionError::TooManyPieces(PieceType::Rook)]
+        );
+    }
+
+    #[test]
+    fn side_not_to_move_in_check() {
+        assert_eq!(
+            errors("4k4/4G4/9/9/9/9/9/9/4K4 b - 1"),
+            vec![PositionError::OpponentInCheck(Color::White)]
+        );
+        // 手番側が王手されているのは問題ない
+        assert_eq!(errors("4k4/4G4/9/9/9/9/9/9/4K4 w - 1"), vec![]);
+    }
+
+    #[test]
+    fn several_problems_are_reported_together() {
+        assert_eq!(
+            errors("P8/9/9/9/9/9/9/9/4K4 b - 1"),
+            vec![
+                PositionError::MissingKing(Color::White),
+                PositionError::DeadPiece {
+                    piece: Piece::new(PieceType::Pawn, Color::Black),
+                    x: 8,
+                    y: 8
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{
+    bitboard::{square, SQUARE_COUNT},
+    board::{hand_index, Boards, HAND_MAX_COUNTS, HAND_PIECE_TYPES},
+    piece::{Color, Piece, PieceType},
+};
+
+// 持ち駒の枚数の上限 (どの種類でも持てる一番多い枚数)
+const MAX_HAND_COUNT: usize = max_hand_count();
+const PIECE_TYPE_COUNT: usize = PieceType::get_max() as usize;
+
+// Zobristハッシュの乱数表
+struct ZobristKeys {
+    // 盤上の駒 [手番][駒の種類][升]
+    board: [[[u64; SQUARE_COUNT]; PIECE_TYPE_COUNT]; 2],
+    // 持ち駒 [手番][HAND_PIECE_TYPESの順][枚数]
+    hand: [[[u64; MAX_HAND_COUNT + 1]; HAND_PIECE_TYPES.len()]; 2],
+    // 後手番
+    turn: u64,
+}
+
+// 乱数表はビルドや乱数ライブラリのバージョンによらず同じ値になるようにsplitmix64で作る
+static KEYS: ZobristKeys = init_keys();
+
+const fn max_hand_count() -> usize {
+    let mut max = 0;
+    let mut i = 0;
+    while i < HAND_MAX_COUNTS.len() {
+        if HAND_MAX_COUNTS[i] as usize > max {
+            max = HAND_MAX_COUNTS[i] as usize;
+        }
+        i += 1;
+    }
+    max
+}
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (state, z ^ (z >> 31))
+}
+
+const fn init_keys() -> ZobristKeys {
+    let mut keys = ZobristKeys {
+        board: [[[0; SQUARE_COUNT]; PIECE_TYPE_COUNT]; 2],
+        hand: [[[0; MAX_HAND_COUNT + 1]; HAND_PIECE_TYPES.len()]; 2],
+        turn: 0,
+    };
+    let mut state = 0;
+    let mut color = 0;
+    while color < 2 {
+        let mut piece_type = 0;
+        while piece_type < PIECE_TYPE_COUNT {
+            let mut sq = 0;
+            while sq < SQUARE_COUNT {
+                let (next, key) = splitmix64(state);
+                state = next;
+                keys.board[color][piece_type][sq] = key;
+                sq += 1;
+            }
+            piece_type += 1;
+        }
+        let mut i = 0;
+        while i < HAND_PIECE_TYPES.len() {
+            // 0枚のときは0にして、持ち駒がない局面のハッシュに影響しないようにする
+            let mut count = 1;
+            while count <= HAND_MAX_COUNTS[i] as usize {
+                let (next, key) = splitmix64(state);
+                state = next;
+                keys.hand[color][i][count] = key;
+                count += 1;
+            }
+            i += 1;
+        }
+        color += 1;
+    }
+    keys.turn = splitmix64(state).1;
+    keys
+}
+
+// 盤上の駒のハッシュ値
+pub fn piece_key(piece: Piece, sq: usize) -> u64 {
+    KEYS.board[piece.color as usize][piece.piece_type as usize - 1][sq]
+}
+
+// 持ち駒の枚数のハッシュ値
+pub fn hand_key(piece_type: PieceType, color: Color, count: usize) -> u64 {
+    hand_index(piece_type).map_or(0, |i| KEYS.hand[color as usize][i][count])
+}
+
+// 後手番のときに加えるハッシュ値
+pub fn turn_key() -> u64 {
+    KEYS.turn
+}
+
+// 盤面・持ち駒・手番からハッシュ値を計算する関数
+pub fn hash(boards: &Boards, turn: Color) -> u64 {
+    let mut key = 0;
+    for (y, row) in boards.board.iter().enumerate() {
+        for (x, piece) in row.iter().enumerate() {
+            if let Some(piece) = piece {
+                key ^= piece_key(*piece, square(x, y));
+            }
+        }
+    }
+    for color in [Color::Black, Color::White] {
+        for piece_type in HAND_PIECE_TYPES {
+            key ^= hand_key(
+                piece_type,
+                color,
+                boards.hand.count(piece_type, color) as usize,
+            );
+        }
+    }
+    if turn == Color::White {
+        key ^= turn_key();
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bitboard::Position,
+        board::{create_legal_moves, do_move, undo_move},
+        sfen::{parse_sfen, INITIAL_SFEN},
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn incremental_key_matches_hash() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let (mut boards, mut turn, _) = parse_sfen(INITIAL_SFEN).unwrap();
+            let mut key = hash(&boards, turn);
+            let mut undos = vec![];
+            for _ in 0..200 {
+                // ときどき何手か戻す
+                if !undos.is_empty() && rng.gen_range(0..4) == 0 {
+                    for _ in 0..rng.gen_range(1..=undos.len().min(3)) {
+                        let undo = undos.pop().unwrap();
+                        undo_move(&mut boards, &undo);
+                        key ^= undo.key;
+                        turn = turn.opponent();
+                        assert_eq!(key, hash(&boards, turn));
+                    }
+                    continue;
+                }
+                let moves = create_legal_moves(&boards, turn);
+                if moves.is_empty() {
+                    break;
+                }
+                let undo = do_move(&mut boards, moves[rng.gen_range(0..moves.len())]);
+                key ^= undo.key;
+                turn = turn.opponent();
+                undos.push(undo);
+                assert_eq!(key, hash(&boards, turn));
+                assert_eq!(key, Position::from_boards(&boards, turn).key());
+            }
+        }
+    }
+}